@@ -1,19 +1,31 @@
 use crate::config::{grafana::to_grafana_dashboard_json, Config, Target, TargetDefault};
-use crate::messages::{EntryDTO, FailureDTO};
+use crate::messages::{EntryDTO, FailureDTO, StateChange, TargetState, Timings};
 use crate::tasks::file::FileReporterTask;
 use crate::utils::{
     file::{read_to_string, to_absolute_pair},
     prometheus as util_prometheus,
 };
-use crate::{server::SonarServer, tasks::http::IntervalRequesterTask};
+use crate::{
+    config::{maintenance, maintenance::MaintenanceWindow, ProbeType},
+    server::SonarServer,
+    tasks::{
+        heartbeat::{HeartbeatMonitorTask, HeartbeatSenders},
+        hook::HookTask,
+        limiter::RateLimiter,
+        notifier::{self, Incidents, NotifierTask},
+        probe,
+        requester::IntervalRequesterTask,
+        state::{Dependencies, StateTrackerTask, TargetStates},
+    },
+};
 use broadcast::RecvError;
+use chrono::Utc;
 use futures::future::{AbortHandle, Abortable};
 use log::*;
 use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
-use prometheus::{Counter, Histogram, HistogramOpts, Opts, Registry};
-use reqwest::Client;
+use prometheus::{Counter, Gauge, Histogram, HistogramOpts, Opts, Registry};
 use std::error::Error;
-use std::{collections::HashMap, path::PathBuf, time::Duration};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
 use tokio::fs::File;
 use tokio::{
     prelude::*,
@@ -21,6 +33,10 @@ use tokio::{
     time::delay_for,
 };
 
+// results are kept for slow receivers, the state trackers need all of them
+const RESULT_CHANNEL_CAPACITY: usize = 16;
+const STATE_CHANGE_CHANNEL_CAPACITY: usize = 100;
+
 pub const NAME: &str = "run";
 pub const ABOUT: &str = "starts the requesting";
 
@@ -37,17 +53,21 @@ pub const THREAD_ARG_TAKES_VALUE: bool = true;
 pub const THREAD_ARG_HELP: &str = "Max number of threads. Default to cores available";
 
 pub struct Command {
-    http_client: Client,
     server_kill_sender: Option<oneshot::Sender<()>>,
     graceful_shutdown_complete_receiver: Option<oneshot::Receiver<()>>,
     server_running: bool,
     prometheus_registry: Option<Registry>,
+    heartbeat_senders: Option<HeartbeatSenders>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    state_changes: Option<broadcast::Sender<StateChange>>,
+    incidents: Incidents,
+    target_states: TargetStates,
     requester_abort_controllers: Option<Vec<AbortHandle>>,
     reporter_abort_controllers: Option<Vec<AbortHandle>>,
 }
 
 impl Command {
-    pub async fn exercute<'a>(config_path: PathBuf, client: Client) -> Result<(), Box<dyn Error>> {
+    pub async fn exercute<'a>(config_path: PathBuf) -> Result<(), Box<dyn Error>> {
         let (abs_config_file, abs_config_folder) = to_absolute_pair(config_path.clone()).await;
 
         let (tx, rx) = std::sync::mpsc::channel::<DebouncedEvent>();
@@ -59,7 +79,7 @@ impl Command {
             .expect("failed to watch config root folder");
 
         // handle initial start run
-        let mut executor = Command::new(client);
+        let mut executor = Command::new();
         executor.handle(abs_config_file.clone()).await;
         debug!(
             "watching for config changes in {}",
@@ -92,13 +112,17 @@ impl Command {
         }
     }
 
-    pub fn new(http_client: Client) -> Self {
+    pub fn new() -> Self {
         Self {
-            http_client,
             server_kill_sender: None,
             graceful_shutdown_complete_receiver: None,
             server_running: false,
             prometheus_registry: None,
+            heartbeat_senders: None,
+            rate_limiter: None,
+            state_changes: None,
+            incidents: Incidents::default(),
+            target_states: TargetStates::default(),
             requester_abort_controllers: None,
             reporter_abort_controllers: None,
         }
@@ -121,10 +145,31 @@ impl Command {
                 return;
             }
             Ok(config) => {
+                if let Err(err) = config.validate_targets() {
+                    error!("invalid config - Please fix: {}", err);
+                    return;
+                }
+                let config = config.hydrate();
+                if let Err(err) = config.validate_maintenance() {
+                    error!("invalid config - Please fix: maintenance window {}", err);
+                    return;
+                }
+                if let Some(Err(err)) = config.rate_limit.as_ref().map(|r| r.validate()) {
+                    error!("invalid config - Please fix: rate_limit {}", err);
+                    return;
+                }
+                if let Err(err) = config.validate_dependencies() {
+                    error!("invalid config - Please fix: {}", err);
+                    return;
+                }
+                if let Err(err) = config.validate_routes() {
+                    error!("invalid config - Please fix: {}", err);
+                    return;
+                }
                 info!("config loaded");
 
                 self.handle_grafana_dashboard(config.clone()).await;
-                let request_data_receivers = self.handle_requesters(config.targets.clone()).await;
+                let request_data_receivers = self.handle_requesters(&config).await;
                 if config.server.is_some() {
                     match config.server.clone() {
                         Some(server_config) => {
@@ -132,6 +177,7 @@ impl Command {
                                 self.handle_prometheus_exporter(
                                     config.targets.clone(),
                                     config.targets_defaults.clone(),
+                                    config.maintenance_windows(),
                                     request_data_receivers,
                                 );
                             }
@@ -156,9 +202,9 @@ impl Command {
     async fn stop_reporters(&mut self) {
         if self.reporter_abort_controllers.is_some() {
             let controllers = self
-                .requester_abort_controllers
+                .reporter_abort_controllers
                 .take()
-                .expect("failed to take requester forceful shutdown handlers");
+                .expect("failed to take reporter forceful shutdown handlers");
             self.stop(controllers).await;
         }
     }
@@ -175,17 +221,43 @@ impl Command {
 
     async fn handle_requesters(
         &mut self,
-        targets: Vec<Target>,
+        config: &Config,
     ) -> Vec<broadcast::Receiver<Result<EntryDTO, FailureDTO>>> {
         // stop/clean out old requesters
         self.stop_reporters().await;
         self.stop_requesters().await;
 
+        let strategy = config.schedule_strategy();
+        let maintenance = config.maintenance_windows();
+        let policy = config.notification_policy();
+        // incidents of removed targets are forgotten, the others stay open across the reload
+        self.incidents
+            .lock()
+            .expect("failed to lock incidents")
+            .retain(|name, _| config.targets.iter().any(|t| t.name.as_ref() == Some(name)));
+        // so are the states, a target that is down stays down until it recovers
+        self.target_states
+            .lock()
+            .expect("failed to lock target states")
+            .retain(|name, _| config.targets.iter().any(|t| t.name.as_ref() == Some(name)));
+        let rate_limit = config.rate_limit.clone();
+
+        // one limiter is shared by all requesters
+        self.rate_limiter = rate_limit.map(|r| Arc::new(RateLimiter::new(r)));
+
         let mut requester_abort_handles = Vec::new();
         let mut reporter_abort_handles = Vec::new();
         let mut request_result_rx = Vec::new();
-        for target in targets {
-            let (broadcast_tx, _broadcast_rx) = channel::<Result<EntryDTO, FailureDTO>>(1);
+        let mut heartbeat_senders = HashMap::new();
+        let (state_tx, _state_rx) = channel::<StateChange>(STATE_CHANGE_CHANNEL_CAPACITY);
+        for target in config.targets.clone() {
+            let target_maintenance: Vec<MaintenanceWindow> = maintenance
+                .iter()
+                .filter(|w| w.applies_to(&target.clone_unwrap_name()))
+                .cloned()
+                .collect();
+            let (broadcast_tx, _broadcast_rx) =
+                channel::<Result<EntryDTO, FailureDTO>>(RESULT_CHANNEL_CAPACITY);
             request_result_rx.push(_broadcast_rx);
 
             // reporters
@@ -204,8 +276,86 @@ impl Command {
                     abort_registration,
                 ));
             }
+            // state
+            let state_tracker = StateTrackerTask::new(
+                broadcast_tx.subscribe(),
+                state_tx.clone(),
+                self.target_states.clone(),
+            );
+            let tracked_target = target.clone();
+            let (abort_handle, abort_registration) = AbortHandle::new_pair();
+            reporter_abort_handles.push(abort_handle);
+            tokio::spawn(Abortable::new(
+                async move {
+                    state_tracker.run(tracked_target).await;
+                },
+                abort_registration,
+            ));
+            // notifiers
+            let notifiers = notifier::for_target(&target, config);
+            if !notifiers.is_empty() {
+                let notifier = NotifierTask::new(
+                    notifiers,
+                    policy.clone(),
+                    self.incidents.clone(),
+                    broadcast_tx.subscribe(),
+                    state_tx.subscribe(),
+                );
+                let notified_target = target.clone();
+                let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                reporter_abort_handles.push(abort_handle);
+                tokio::spawn(Abortable::new(
+                    async move {
+                        notifier.run(notified_target).await;
+                    },
+                    abort_registration,
+                ));
+            }
+            // hooks
+            if target.on_down.is_some() || target.on_up.is_some() {
+                let hook_runner = HookTask::new(state_tx.subscribe(), self.target_states.clone());
+                let hooked_target = target.clone();
+                let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                reporter_abort_handles.push(abort_handle);
+                tokio::spawn(Abortable::new(
+                    async move {
+                        hook_runner.run(hooked_target).await;
+                    },
+                    abort_registration,
+                ));
+            }
+            let dependencies = Dependencies::new(&target, self.target_states.clone());
+            // heartbeats are pushed to the server instead of requested
+            if target.clone_unwrap_type() == ProbeType::Heartbeat {
+                let (monitor, heartbeat_tx) =
+                    HeartbeatMonitorTask::new(broadcast_tx, target_maintenance, dependencies);
+                heartbeat_senders.insert(target.clone_unwrap_name(), heartbeat_tx);
+                let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                requester_abort_handles.push(abort_handle);
+                tokio::spawn(Abortable::new(
+                    async move {
+                        monitor.run(target).await;
+                    },
+                    abort_registration,
+                ));
+                continue;
+            }
             // requesters
-            let requester = IntervalRequesterTask::new(self.http_client.clone(), broadcast_tx);
+            let probe = match probe::from_target(&target).await {
+                Ok(probe) => probe,
+                Err(err) => {
+                    error!("{} - failed to create probe: {}", target.describe(), err);
+                    continue;
+                }
+            };
+            let requester = IntervalRequesterTask::new(
+                probe,
+                broadcast_tx,
+                strategy.clone(),
+                target_maintenance,
+                self.rate_limiter.clone(),
+                dependencies,
+            );
             let (abort_handle, abort_registration) = AbortHandle::new_pair();
             requester_abort_handles.push(abort_handle);
             tokio::spawn(Abortable::new(
@@ -216,6 +366,9 @@ impl Command {
             ));
         }
         self.requester_abort_controllers = Some(requester_abort_handles);
+        self.reporter_abort_controllers = Some(reporter_abort_handles);
+        self.heartbeat_senders = Some(heartbeat_senders);
+        self.state_changes = Some(state_tx);
 
         request_result_rx
     }
@@ -224,10 +377,12 @@ impl Command {
         &mut self,
         targets: Vec<Target>,
         target_defaults: Option<TargetDefault>,
+        maintenance: Vec<MaintenanceWindow>,
         receivers: Vec<broadcast::Receiver<Result<EntryDTO, FailureDTO>>>,
     ) {
         let mut timers: HashMap<String, Histogram> = HashMap::new();
         let mut counters: HashMap<String, Counter> = HashMap::new();
+        let mut gauges: HashMap<String, Gauge> = HashMap::new();
         let registry = Registry::new();
 
         for target in &targets {
@@ -240,6 +395,29 @@ impl Command {
             .expect("failed to create success counter");
             counters.insert(counter_success_name.clone(), counter_success.clone());
 
+            let flapping_name = util_prometheus::flapping_name(target.clone_unwrap_name());
+            let flapping = Gauge::with_opts(Opts::new(
+                flapping_name.clone(),
+                String::from("1 while the target is flapping"),
+            ))
+            .expect("failed to create flapping gauge");
+            gauges.insert(flapping_name, flapping.clone());
+            registry
+                .register(Box::new(flapping))
+                .expect("unable to register flapping gauge");
+
+            let counter_retries_name =
+                util_prometheus::counter_retries_name(target.clone_unwrap_name());
+            let counter_retries = Counter::with_opts(Opts::new(
+                counter_retries_name.clone(),
+                String::from("Number of retried requests"),
+            ))
+            .expect("failed to create retries counter");
+            counters.insert(counter_retries_name, counter_retries.clone());
+            registry
+                .register(Box::new(counter_retries))
+                .expect("unable to register retries counter");
+
             let timer_name = util_prometheus::timer_name(target.clone_unwrap_name());
             let prometheus_response_time_bucket =
                 if target.prometheus_response_time_bucket.is_none() {
@@ -256,24 +434,190 @@ impl Command {
                 };
             let request_time_opts =
                 HistogramOpts::new(timer_name.clone(), String::from("latency in ms"))
-                    .buckets(prometheus_response_time_bucket);
+                    .buckets(prometheus_response_time_bucket.clone());
             let request_time =
                 Histogram::with_opts(request_time_opts).expect("unable to create timer");
 
             timers.insert(timer_name.clone(), request_time.clone());
 
+            for phase in Timings::PHASES.iter() {
+                let phase_timer_name =
+                    util_prometheus::phase_timer_name(target.clone_unwrap_name(), phase);
+                let phase_time = Histogram::with_opts(
+                    HistogramOpts::new(
+                        phase_timer_name.clone(),
+                        format!("{} latency in ms", phase),
+                    )
+                    .buckets(prometheus_response_time_bucket.clone()),
+                )
+                .expect("unable to create phase timer");
+                timers.insert(phase_timer_name, phase_time.clone());
+                registry
+                    .register(Box::new(phase_time))
+                    .expect("unable to register phase timer");
+            }
+
             registry
                 .register(Box::new(request_time))
                 .expect("unable to register timer");
             registry
                 .register(Box::new(counter_success))
                 .expect("unable to register timer");
+
+            let interval_name = util_prometheus::interval_name(target.clone_unwrap_name());
+            let interval = Gauge::with_opts(Opts::new(
+                interval_name.clone(),
+                String::from("seconds until the next request, interval_on_failure while failing"),
+            ))
+            .expect("failed to create interval gauge");
+            gauges.insert(interval_name, interval.clone());
+            registry
+                .register(Box::new(interval))
+                .expect("unable to register interval gauge");
+
+            let in_maintenance_name =
+                util_prometheus::in_maintenance_name(target.clone_unwrap_name());
+            let in_maintenance = Gauge::with_opts(Opts::new(
+                in_maintenance_name.clone(),
+                String::from("1 while the target is in a maintenance window"),
+            ))
+            .expect("failed to create in maintenance gauge");
+            gauges.insert(in_maintenance_name, in_maintenance.clone());
+            registry
+                .register(Box::new(in_maintenance))
+                .expect("unable to register in maintenance gauge");
+
+            let up_name = util_prometheus::up_name(target.clone_unwrap_name());
+            let up = Gauge::with_opts(Opts::new(
+                up_name.clone(),
+                String::from("1 while the target is up or degraded, 0 while down and -1 until the first result"),
+            ))
+            .expect("failed to create up gauge");
+            up.set(-1.0);
+            gauges.insert(up_name, up.clone());
+            registry
+                .register(Box::new(up))
+                .expect("unable to register up gauge");
+
+            let counter_state_changes_name =
+                util_prometheus::counter_state_changes_name(target.clone_unwrap_name());
+            let counter_state_changes = Counter::with_opts(Opts::new(
+                counter_state_changes_name.clone(),
+                String::from("Number of state changes"),
+            ))
+            .expect("failed to create state changes counter");
+            counters.insert(counter_state_changes_name, counter_state_changes.clone());
+            registry
+                .register(Box::new(counter_state_changes))
+                .expect("unable to register state changes counter");
+
+            if target.url.starts_with("https://") {
+                let cert_expiry_name =
+                    util_prometheus::cert_expiry_name(target.clone_unwrap_name());
+                let cert_expiry = Gauge::with_opts(Opts::new(
+                    cert_expiry_name.clone(),
+                    String::from("seconds until the certificate expires"),
+                ))
+                .expect("failed to create cert expiry gauge");
+                gauges.insert(cert_expiry_name, cert_expiry.clone());
+                registry
+                    .register(Box::new(cert_expiry))
+                    .expect("unable to register cert expiry gauge");
+            }
+        }
+        if let Some(limiter) = &self.rate_limiter {
+            registry
+                .register(Box::new(limiter.delayed_counter()))
+                .expect("unable to register rate limit counter");
+            registry
+                .register(Box::new(limiter.skipped_counter()))
+                .expect("unable to register rate limit counter");
         }
         self.prometheus_registry = Some(registry);
 
+        // maintenance windows start and end without any requests, so the gauges are updated on their own
+        let maintenance_gauges: Vec<(String, Gauge)> = targets
+            .iter()
+            .map(|t| {
+                let name = t.clone_unwrap_name();
+                let gauge = gauges
+                    .get(&util_prometheus::in_maintenance_name(name.clone()))
+                    .expect("could not find in maintenance gauge by key")
+                    .clone();
+                (name, gauge)
+            })
+            .collect();
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        self.reporter_abort_controllers
+            .get_or_insert_with(Vec::new)
+            .push(abort_handle);
+        tokio::spawn(Abortable::new(
+            async move {
+                loop {
+                    let now = Utc::now();
+                    for (name, gauge) in &maintenance_gauges {
+                        let active = maintenance::active_action(&maintenance, name, now).is_some();
+                        gauge.set(if active { 1.0 } else { 0.0 });
+                    }
+                    delay_for(Duration::from_secs(1)).await;
+                }
+            },
+            abort_registration,
+        ));
+
+        if let Some(state_changes) = &self.state_changes {
+            let mut state_changes = state_changes.subscribe();
+            let counters = counters.clone();
+            let gauges = gauges.clone();
+            let (abort_handle, abort_registration) = AbortHandle::new_pair();
+            self.reporter_abort_controllers
+                .get_or_insert_with(Vec::new)
+                .push(abort_handle);
+            tokio::spawn(Abortable::new(
+                async move {
+                    loop {
+                        let change = match state_changes.recv().await {
+                            Ok(change) => change,
+                            Err(RecvError::Closed) => break,
+                            Err(RecvError::Lagged(n)) => {
+                                warn!("prometheus state receiver is lagging behind with: {}", n);
+                                continue;
+                            }
+                        };
+                        let name = change.target.clone_unwrap_name();
+                        counters
+                            .get(&util_prometheus::counter_state_changes_name(name.clone()))
+                            .expect("could not find state changes counter by key")
+                            .inc();
+                        gauges
+                            .get(&util_prometheus::flapping_name(name.clone()))
+                            .expect("could not find flapping gauge by key")
+                            .set(if change.to == TargetState::Flapping {
+                                1.0
+                            } else {
+                                0.0
+                            });
+                        let up = match change.to {
+                            TargetState::Up | TargetState::Degraded => 1.0,
+                            TargetState::Down => 0.0,
+                            TargetState::Unknown => -1.0,
+                            // keeps the value from before it started flapping
+                            TargetState::Flapping => continue,
+                        };
+                        gauges
+                            .get(&util_prometheus::up_name(name))
+                            .expect("could not find up gauge by key")
+                            .set(up);
+                    }
+                },
+                abort_registration,
+            ));
+        }
+
         for mut r in receivers {
             let timers = timers.clone();
             let counters = counters.clone();
+            let gauges = gauges.clone();
             tokio::spawn(async move {
                 loop {
                     debug!("started prometheus metrics receiver");
@@ -286,10 +630,42 @@ impl Command {
                                     ))
                                     .expect("could not find success counter by key")
                                     .inc();
+                                counters
+                                    .get(&util_prometheus::counter_retries_name(
+                                        r.target.clone_unwrap_name(),
+                                    ))
+                                    .expect("could not find retries counter by key")
+                                    .inc_by((r.attempts - 1) as f64);
                                 timers
                                     .get(&util_prometheus::timer_name(r.target.clone_unwrap_name()))
                                     .expect("could not find timer by key")
                                     .observe(r.latency as f64);
+                                for (phase, latency) in r.timings.phases().iter() {
+                                    timers
+                                        .get(&util_prometheus::phase_timer_name(
+                                            r.target.clone_unwrap_name(),
+                                            phase,
+                                        ))
+                                        .expect("could not find phase timer by key")
+                                        .observe(*latency as f64);
+                                }
+                                if let Some(interval_ms) = r.interval_ms {
+                                    gauges
+                                        .get(&util_prometheus::interval_name(
+                                            r.target.clone_unwrap_name(),
+                                        ))
+                                        .expect("could not find interval gauge by key")
+                                        .set(interval_ms as f64 / 1000.0);
+                                }
+                                // the gauge only exists for https targets
+                                if let (Some(expires), Some(gauge)) = (
+                                    r.cert_expires_timestamp_seconds,
+                                    gauges.get(&util_prometheus::cert_expiry_name(
+                                        r.target.clone_unwrap_name(),
+                                    )),
+                                ) {
+                                    gauge.set((expires - Utc::now().timestamp()) as f64);
+                                }
                             }
                             Err(err) => {
                                 timers
@@ -298,6 +674,29 @@ impl Command {
                                     ))
                                     .expect("could not find timer by name")
                                     .observe(err.latency as f64);
+                                counters
+                                    .get(&util_prometheus::counter_retries_name(
+                                        err.target.clone_unwrap_name(),
+                                    ))
+                                    .expect("could not find retries counter by key")
+                                    .inc_by((err.attempts - 1) as f64);
+                                if let Some(interval_ms) = err.interval_ms {
+                                    gauges
+                                        .get(&util_prometheus::interval_name(
+                                            err.target.clone_unwrap_name(),
+                                        ))
+                                        .expect("could not find interval gauge by key")
+                                        .set(interval_ms as f64 / 1000.0);
+                                }
+                                // the gauge only exists for https targets
+                                if let (Some(expires), Some(gauge)) = (
+                                    err.cert_expires_timestamp_seconds,
+                                    gauges.get(&util_prometheus::cert_expiry_name(
+                                        err.target.clone_unwrap_name(),
+                                    )),
+                                ) {
+                                    gauge.set((expires - Utc::now().timestamp()) as f64);
+                                }
                             }
                         },
                         Err(err) => {
@@ -357,7 +756,11 @@ impl Command {
 
     async fn start_server(&mut self, config: Config) {
         let config = config.server.expect("failed to unwrap server config");
-        let mut server = SonarServer::new(config, self.prometheus_registry.take());
+        let mut server = SonarServer::new(
+            config,
+            self.prometheus_registry.take(),
+            self.heartbeat_senders.take().unwrap_or_default(),
+        );
         let (server_kill_sender, graceful_shutdown_complete_receiver) = server.start();
         self.server_kill_sender = Some(server_kill_sender);
         self.graceful_shutdown_complete_receiver = Some(graceful_shutdown_complete_receiver);
@@ -385,6 +788,15 @@ impl Command {
             self.server_running = false;
         }
         if !config.server.is_some() {
+            if self
+                .heartbeat_senders
+                .as_ref()
+                .is_some_and(|senders| !senders.is_empty())
+            {
+                warn!(
+                    "heartbeat targets need a server to receive pings - add a server to the config"
+                );
+            }
             return;
         }
         self.start_server(config).await;
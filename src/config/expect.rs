@@ -0,0 +1,350 @@
+use crate::utils::factory;
+use duration_string::DurationString;
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::Duration;
+
+// assertions a response must pass before it is counted as a success
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Expect {
+    // allowed status codes, either a single code like 200 or a range like "200-299"
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub status: Option<Vec<StatusCodeMatch>>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub body_contains: Option<String>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub body_regex: Option<Pattern>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub json: Option<Vec<JsonMatch>>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub headers: Option<Vec<HeaderMatch>>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub max_latency: Option<DurationString>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StatusCodeMatch {
+    Code(u16),
+    Range(StatusCodeRange),
+}
+
+impl StatusCodeMatch {
+    pub fn matches(&self, status: u16) -> bool {
+        match self {
+            StatusCodeMatch::Code(code) => *code == status,
+            StatusCodeMatch::Range(range) => range.from <= status && status <= range.to,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct StatusCodeRange {
+    pub from: u16,
+    pub to: u16,
+}
+
+impl TryFrom<String> for StatusCodeRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let parts: Vec<&str> = s.splitn(2, '-').map(|p| p.trim()).collect();
+        let parse = |p: &str| {
+            p.parse::<u16>()
+                .map_err(|_| format!("invalid status code range: {}", s))
+        };
+        match parts.as_slice() {
+            [code] => {
+                let code = parse(code)?;
+                Ok(Self {
+                    from: code,
+                    to: code,
+                })
+            }
+            [from, to] => {
+                let (from, to) = (parse(from)?, parse(to)?);
+                if from > to {
+                    return Err(format!("invalid status code range: {}", s));
+                }
+                Ok(Self { from, to })
+            }
+            _ => Err(format!("invalid status code range: {}", s)),
+        }
+    }
+}
+
+impl From<StatusCodeRange> for String {
+    fn from(range: StatusCodeRange) -> Self {
+        format!("{}-{}", range.from, range.to)
+    }
+}
+
+// a value in a JSON response body found by a JSON pointer, e.g. /data/status
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JsonMatch {
+    pub pointer: String,
+    // if not set the pointer only has to exist
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub equals: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeaderMatch {
+    pub name: String,
+    // regex the header value must match. If not set the header only has to be present
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub matches: Option<Pattern>,
+}
+
+// a regex compiled once when the config is loaded, so an invalid pattern is a config error
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn is_match(&self, value: &str) -> bool {
+        self.0.is_match(value)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Regex::new(&s)
+            .map(Pattern)
+            .map_err(|err| format!("invalid regex '{}': {}", s, err))
+    }
+}
+
+impl From<Pattern> for String {
+    fn from(pattern: Pattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.as_str())
+    }
+}
+
+impl Expect {
+    // the response body is only read when an assertion looks at it
+    pub fn needs_body(&self) -> bool {
+        self.body_contains.is_some() || self.body_regex.is_some() || self.json.is_some()
+    }
+
+    // returns the reason of the first assertion that fails
+    pub fn verify(
+        &self,
+        status: u16,
+        headers: &HeaderMap,
+        body: Option<&str>,
+        latency: Duration,
+    ) -> Result<(), String> {
+        if let Some(max_latency) = &self.max_latency {
+            let max_latency: Duration = (*max_latency).into();
+            if latency > max_latency {
+                return Err(format!(
+                    "latency {}ms exceeded max latency {}ms",
+                    latency.as_millis(),
+                    max_latency.as_millis()
+                ));
+            }
+        }
+        if let Some(codes) = &self.status {
+            if !codes.iter().any(|c| c.matches(status)) {
+                return Err(format!("unexpected status code {}", status));
+            }
+        }
+        if let Some(header_matches) = &self.headers {
+            for header_match in header_matches {
+                let value = match headers.get(header_match.name.as_str()) {
+                    Some(v) => v.to_str().unwrap_or(""),
+                    None => return Err(format!("missing header {}", header_match.name)),
+                };
+                if let Some(pattern) = &header_match.matches {
+                    if !pattern.is_match(value) {
+                        return Err(format!(
+                            "header {} value '{}' does not match '{}'",
+                            header_match.name, value, pattern
+                        ));
+                    }
+                }
+            }
+        }
+        let body = body.unwrap_or("");
+        if let Some(needle) = &self.body_contains {
+            if !body.contains(needle.as_str()) {
+                return Err(format!("body does not contain '{}'", needle));
+            }
+        }
+        if let Some(pattern) = &self.body_regex {
+            if !pattern.is_match(body) {
+                return Err(format!("body does not match '{}'", pattern));
+            }
+        }
+        if let Some(json_matches) = &self.json {
+            let json: serde_json::Value = serde_json::from_str(body)
+                .map_err(|err| format!("body is not valid json: {}", err))?;
+            for json_match in json_matches {
+                let value = match json.pointer(&json_match.pointer) {
+                    Some(v) => v,
+                    None => return Err(format!("json pointer {} not found", json_match.pointer)),
+                };
+                if let Some(expected) = &json_match.equals {
+                    if value != expected {
+                        return Err(format!(
+                            "json pointer {} is {} expected {}",
+                            json_match.pointer, value, expected
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderValue, CONTENT_TYPE};
+
+    const BODY: &str = r#"{"status":"up","version":2,"checks":{"db":true}}"#;
+
+    fn verify(expect: &str, status: u16, body: &str) -> Result<(), String> {
+        let expect: Expect = serde_yaml::from_str(expect).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        expect.verify(status, &headers, Some(body), Duration::from_millis(100))
+    }
+
+    #[test]
+    fn checks_the_status() {
+        let cases = [
+            ("status: [200]", 200, Ok(())),
+            ("status: [200, 204]", 204, Ok(())),
+            ("status: [\"200-299\"]", 299, Ok(())),
+            ("status: [\"300-399\", 404]", 404, Ok(())),
+            (
+                "status: [\"200-299\"]",
+                301,
+                Err("unexpected status code 301"),
+            ),
+            ("status: [200]", 500, Err("unexpected status code 500")),
+        ];
+        for (expect, status, result) in cases.iter() {
+            assert_eq!(
+                verify(expect, *status, BODY),
+                result.map_err(String::from),
+                "{} with {}",
+                expect,
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_status_ranges() {
+        assert!(serde_yaml::from_str::<Expect>("status: [\"299-200\"]").is_err());
+        assert!(serde_yaml::from_str::<Expect>("status: [\"2xx\"]").is_err());
+    }
+
+    #[test]
+    fn checks_the_body() {
+        let cases = [
+            ("body_contains: '\"up\"'", Ok(())),
+            ("body_contains: down", Err("body does not contain 'down'")),
+            ("body_regex: '\"version\":\\d+'", Ok(())),
+            ("body_regex: '^up$'", Err("body does not match '^up$'")),
+        ];
+        for (expect, result) in cases.iter() {
+            assert_eq!(
+                verify(expect, 200, BODY),
+                result.map_err(String::from),
+                "{}",
+                expect
+            );
+        }
+        assert!(serde_yaml::from_str::<Expect>("body_regex: '('").is_err());
+    }
+
+    #[test]
+    fn checks_json_pointers() {
+        let cases = [
+            ("json:\n  - pointer: /checks/db\n    equals: true", Ok(())),
+            ("json:\n  - pointer: /version", Ok(())),
+            (
+                "json:\n  - pointer: /checks/cache",
+                Err("json pointer /checks/cache not found"),
+            ),
+            (
+                "json:\n  - pointer: /status\n    equals: down",
+                Err("json pointer /status is \"up\" expected \"down\""),
+            ),
+            // a number is not equal to the same digits in a string
+            (
+                "json:\n  - pointer: /version\n    equals: \"2\"",
+                Err("json pointer /version is 2 expected \"2\""),
+            ),
+        ];
+        for (expect, result) in cases.iter() {
+            assert_eq!(
+                verify(expect, 200, BODY),
+                result.map_err(String::from),
+                "{}",
+                expect
+            );
+        }
+        let not_json = verify("json:\n  - pointer: /status", 200, "<html>up</html>");
+        assert!(not_json
+            .unwrap_err()
+            .starts_with("body is not valid json: "));
+    }
+
+    #[test]
+    fn checks_headers() {
+        let cases = [
+            ("headers:\n  - name: content-type", Ok(())),
+            (
+                "headers:\n  - name: Content-Type\n    matches: ^application/json",
+                Ok(()),
+            ),
+            (
+                "headers:\n  - name: content-type\n    matches: ^text/",
+                Err("header content-type value 'application/json' does not match '^text/'"),
+            ),
+            ("headers:\n  - name: etag", Err("missing header etag")),
+        ];
+        for (expect, result) in cases.iter() {
+            assert_eq!(
+                verify(expect, 200, BODY),
+                result.map_err(String::from),
+                "{}",
+                expect
+            );
+        }
+    }
+
+    #[test]
+    fn checks_the_latency() {
+        assert_eq!(verify("max_latency: 1s", 200, BODY), Ok(()));
+        assert_eq!(
+            verify("max_latency: 50ms", 200, BODY),
+            Err(String::from("latency 100ms exceeded max latency 50ms"))
+        );
+    }
+}
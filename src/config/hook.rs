@@ -0,0 +1,38 @@
+use crate::utils::factory;
+use duration_string::DurationString;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const DEFAULT_HOOK_TIMEOUT: &str = "30s";
+
+// a command that is executed when a target changes state, e.g. to restart a service.
+// Placeholders like {{name}}, {{url}}, {{reason}} and {{latency}} are replaced in the args and env values
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExecHook {
+    // the program to run, it is not run through a shell
+    pub command: String,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub env: Option<HashMap<String, String>>,
+    // the command is killed when it runs longer
+    #[serde(
+        default = "ExecHook::some_default_timeout",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timeout: Option<DurationString>,
+}
+
+impl ExecHook {
+    pub fn some_default_timeout() -> Option<DurationString> {
+        Some(
+            DurationString::from_string(String::from(DEFAULT_HOOK_TIMEOUT))
+                .expect("failed to create from duration string"),
+        )
+    }
+
+    pub fn unwrap_timeout(&self) -> Duration {
+        self.timeout.expect("failed to get timeout").into()
+    }
+}
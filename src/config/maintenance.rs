@@ -0,0 +1,223 @@
+use crate::utils::{factory, schedule::parse_cron};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use duration_string::DurationString;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use strum_macros::Display;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum MaintenanceAction {
+    // no requests are made during the window
+    Pause,
+    // requests are made and their results are tagged as maintenance
+    Tag,
+}
+
+// a period where failures are expected. Either one-off with start and end or
+// recurring with a cron schedule and a duration
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // rfc3339 timestamps, e.g. 2020-05-01T22:00:00+02:00
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub end: Option<DateTime<Utc>>,
+    // cron expression of when the window starts, e.g. "0 2 * * SUN"
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<CronSchedule>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub duration: Option<DurationString>,
+    // timezone of the schedule, defaults to UTC
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<Tz>,
+    // names of the targets in maintenance, all targets if not set
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub targets: Option<Vec<String>>,
+    #[serde(
+        default = "MaintenanceWindow::some_default_action",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub action: Option<MaintenanceAction>,
+}
+
+impl MaintenanceWindow {
+    fn some_default_action() -> Option<MaintenanceAction> {
+        Some(MaintenanceAction::Pause)
+    }
+
+    pub fn clone_unwrap_action(&self) -> MaintenanceAction {
+        self.action.clone().expect("failed to get action")
+    }
+
+    // describes the window in errors and logs
+    pub fn describe(&self) -> String {
+        match (&self.name, &self.schedule) {
+            (Some(name), _) => name.clone(),
+            (None, Some(schedule)) => schedule.to_string(),
+            (None, None) => String::from("maintenance window"),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.start, self.end, &self.schedule, self.duration) {
+            (Some(start), Some(end), None, None) if start < end => Ok(()),
+            (Some(_), Some(_), None, None) => {
+                Err(format!("{} - start must be before end", self.describe()))
+            }
+            (None, None, Some(_), Some(_)) => Ok(()),
+            _ => Err(format!(
+                "{} - needs either start and end or schedule and duration",
+                self.describe()
+            )),
+        }
+    }
+
+    pub fn applies_to(&self, target_name: &str) -> bool {
+        match &self.targets {
+            Some(targets) => targets.iter().any(|t| t == target_name),
+            None => true,
+        }
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            return start <= now && now < end;
+        }
+        let (schedule, duration) = match (&self.schedule, self.duration) {
+            (Some(schedule), Some(duration)) => (&schedule.schedule, duration),
+            _ => return false,
+        };
+        let duration = match chrono::Duration::from_std(duration.into()) {
+            Ok(duration) => duration,
+            _ => return false,
+        };
+        // active if the window started within the last duration
+        let timezone = self.timezone.unwrap_or(Tz::UTC);
+        let window_start = (now - duration).with_timezone(&timezone);
+        match schedule.after(&window_start).next() {
+            Some(started) => started.with_timezone(&Utc) <= now,
+            None => false,
+        }
+    }
+}
+
+// a cron expression parsed once when the config is loaded, so an invalid one is a config error
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronSchedule {
+    expression: String,
+    schedule: Schedule,
+}
+
+impl PartialEq for CronSchedule {
+    fn eq(&self, other: &Self) -> bool {
+        self.expression == other.expression
+    }
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = String;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        let schedule = parse_cron(&expression)
+            .map_err(|err| format!("invalid schedule '{}': {}", expression, err))?;
+        Ok(Self {
+            expression,
+            schedule,
+        })
+    }
+}
+
+impl From<CronSchedule> for String {
+    fn from(schedule: CronSchedule) -> Self {
+        schedule.expression
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+// the action of the active windows for a target. Pause wins over tag
+pub fn active_action(
+    windows: &[MaintenanceWindow],
+    target_name: &str,
+    now: DateTime<Utc>,
+) -> Option<MaintenanceAction> {
+    windows
+        .iter()
+        .filter(|w| w.applies_to(target_name) && w.is_active(now))
+        .map(|w| w.clone_unwrap_action())
+        .max_by_key(|action| *action == MaintenanceAction::Pause)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn window(yaml: &str) -> MaintenanceWindow {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn is_active_between_start_and_end() {
+        let window = window("start: 2020-05-01T22:00:00Z\nend: 2020-05-01T23:00:00Z");
+        assert!(!window.is_active(Utc.ymd(2020, 5, 1).and_hms(21, 59, 59)));
+        assert!(window.is_active(Utc.ymd(2020, 5, 1).and_hms(22, 0, 0)));
+        assert!(!window.is_active(Utc.ymd(2020, 5, 1).and_hms(23, 0, 0)));
+    }
+
+    #[test]
+    fn is_active_for_the_duration_after_each_fire_time() {
+        // sundays at 2 in copenhagen, which is 0 utc in summer
+        let window =
+            window("schedule: \"0 2 * * SUN\"\nduration: 30m\ntimezone: Europe/Copenhagen");
+        assert!(window.is_active(Utc.ymd(2020, 5, 3).and_hms(0, 0, 0)));
+        assert!(window.is_active(Utc.ymd(2020, 5, 3).and_hms(0, 29, 59)));
+        assert!(!window.is_active(Utc.ymd(2020, 5, 3).and_hms(0, 30, 0)));
+        assert!(!window.is_active(Utc.ymd(2020, 5, 3).and_hms(2, 0, 0)));
+        assert!(!window.is_active(Utc.ymd(2020, 5, 4).and_hms(0, 0, 0)));
+    }
+
+    #[test]
+    fn keeps_the_expression_of_the_schedule() {
+        let window = window("schedule: \"0 2 * * SUN\"\nduration: 30m");
+        assert_eq!(window.describe(), "0 2 * * SUN");
+        let yaml = serde_yaml::to_string(&window).unwrap();
+        assert!(yaml.contains("schedule: 0 2 * * SUN"));
+        assert!(serde_yaml::from_str::<MaintenanceWindow>("schedule: \"at 2am\"").is_err());
+    }
+
+    #[test]
+    fn pausing_wins_over_tagging() {
+        let windows = vec![
+            window("start: 2020-05-01T22:00:00Z\nend: 2020-05-01T23:00:00Z\naction: tag"),
+            window(
+                "start: 2020-05-01T22:30:00Z\nend: 2020-05-01T23:00:00Z\ntargets: [api]\naction: pause",
+            ),
+        ];
+        let now = Utc.ymd(2020, 5, 1).and_hms(22, 45, 0);
+        assert_eq!(
+            active_action(&windows, "api", now),
+            Some(MaintenanceAction::Pause)
+        );
+        assert_eq!(
+            active_action(&windows, "web", now),
+            Some(MaintenanceAction::Tag)
+        );
+        assert_eq!(
+            active_action(&windows, "api", Utc.ymd(2020, 5, 1).and_hms(21, 0, 0)),
+            None
+        );
+    }
+}
@@ -1,19 +1,40 @@
-use crate::utils::factory;
+use crate::utils::{factory, file::read_to_string, net::host_and_port, schedule::parse_cron};
+use chrono_tz::Tz;
 use duration_string::DurationString;
+use expect::{Expect, HeaderMatch, Pattern, StatusCodeMatch, StatusCodeRange};
+use hook::ExecHook;
+use maintenance::{CronSchedule, MaintenanceAction, MaintenanceWindow};
+use notification::{Alertmanager, Email, Exec, NotificationPolicy, Route, Webhook};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::time::Duration;
 use strum_macros::Display;
 
+pub mod expect;
 pub mod grafana;
+pub mod hook;
+pub mod maintenance;
+pub mod notification;
 
 const DEFAULT_MAX_CONCURRENT: u32 = 1;
 const DEFAULT_INTERVAL: &str = "1m";
 const DEFAULT_TIMEOUT: &str = "5s";
+const DEFAULT_CERT_WARN_BEFORE: &str = "2w";
+const DEFAULT_RETRY_DELAY: &str = "1s";
+const DEFAULT_INTERVAL_RECOVER_AFTER: u32 = 3;
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+const DEFAULT_RECOVERY_THRESHOLD: u32 = 1;
+const DEFAULT_FLAPPING_WINDOW: u32 = 10;
+const DEFAULT_FLAPPING_RATIO: f64 = 0.5;
 
 const DEFAULT_SERVER_IP: &str = "0.0.0.0";
 const DEFAULT_SERVER_PORT: u16 = 8080;
 const DEFAULT_SERVER_HEALTH_ENDPOINT: &str = "/health";
 const DEFAULT_SERVER_PROMETHEUS_ENDPOINT: &str = "/metrics";
 const DEFAULT_GRAFANA_JSON_PATH: &str = "/opt/sonar/dashboards/sonar.json";
+// the target of the generated config
+const EXAMPLE_URL: &str = "https://example.com";
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Display)]
 pub enum ReportOn {
@@ -22,6 +43,331 @@ pub enum ReportOn {
     Both,
 }
 
+// how the first requests of the targets are spread out
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ScheduleStrategy {
+    // every target starts right away
+    Burst,
+    // each target starts at a fixed point in its interval derived from its name
+    Spread,
+    // like spread, and each request is delayed by up to a tenth of the interval
+    RandomJitter,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    #[serde(
+        default = "ScheduleConfig::some_default_strategy",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub strategy: Option<ScheduleStrategy>,
+}
+
+impl ScheduleConfig {
+    fn some_default_strategy() -> Option<ScheduleStrategy> {
+        Some(ScheduleStrategy::Burst)
+    }
+}
+
+// limits on outgoing requests shared by all targets
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub max_requests_per_second: Option<u32>,
+    // limits for each hostname
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub per_host: Option<HostRateLimit>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostRateLimit {
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub max_concurrent: Option<u32>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub max_requests_per_second: Option<u32>,
+}
+
+impl RateLimit {
+    // a limit of 0 would hold back every request
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_requests_per_second == Some(0) {
+            return Err(String::from("max_requests_per_second must be at least 1"));
+        }
+        let per_host = self.per_host.clone().unwrap_or_default();
+        if per_host.max_requests_per_second == Some(0) {
+            return Err(String::from(
+                "per_host.max_requests_per_second must be at least 1",
+            ));
+        }
+        if per_host.max_concurrent == Some(0) {
+            return Err(String::from("per_host.max_concurrent must be at least 1"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ProbeType {
+    Http,
+    Tcp,
+    Dns,
+    Grpc,
+    Websocket,
+    Heartbeat,
+}
+
+// how serious it is when a target goes down, used to route notifications
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+// options for http targets
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HttpOptions {
+    // follow up to 10 redirects, enabled if not set
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub follow_redirects: Option<bool>,
+    // speak http/2 without negotiating it first. For https the h2 protocol is requested in the handshake
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub http2: Option<bool>,
+}
+
+impl HttpOptions {
+    pub fn unwrap_follow_redirects(&self) -> bool {
+        self.follow_redirects.unwrap_or(true)
+    }
+
+    pub fn unwrap_http2(&self) -> bool {
+        self.http2.unwrap_or(false)
+    }
+}
+
+// options for tcp targets. The url of a tcp target is host:port
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TcpOptions {
+    // the server must greet with data starting with this, e.g. "SSH-" or "220 "
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    // written to the connection after the banner has been read
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub send: Option<String>,
+    // the reply to send must contain this
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub expect: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "UPPERCASE")]
+#[strum(serialize_all = "UPPERCASE")]
+pub enum DnsRecordType {
+    A,
+    Aaaa,
+    Cname,
+    Txt,
+}
+
+impl DnsRecordType {
+    // the numeric type used in dns messages
+    pub fn code(&self) -> u16 {
+        match self {
+            DnsRecordType::A => 1,
+            DnsRecordType::Cname => 5,
+            DnsRecordType::Txt => 16,
+            DnsRecordType::Aaaa => 28,
+        }
+    }
+}
+
+// options for dns targets. The url of a dns target is the name to resolve
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DnsOptions {
+    // ip or ip:port of the resolver, defaults to the first nameserver in /etc/resolv.conf
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<String>,
+    // record type to query, defaults to A
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub record: Option<DnsRecordType>,
+    // every value must be in the answer set, e.g. an ip for A records or the text of a TXT record
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub expect: Option<Vec<String>>,
+}
+
+impl DnsOptions {
+    pub fn clone_unwrap_record(&self) -> DnsRecordType {
+        self.record.clone().unwrap_or(DnsRecordType::A)
+    }
+}
+
+// options for grpc targets. The url of a grpc target is host:port
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GrpcOptions {
+    // service passed to the health check, if not set the overall health of the server is checked
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub tls: Option<bool>,
+}
+
+// options for websocket targets. The url of a websocket target is ws:// or wss://
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WebsocketOptions {
+    // text message sent after the handshake
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub send: Option<String>,
+    // a message containing this must be received before the timeout
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub expect: Option<String>,
+}
+
+// options for heartbeat targets. Instead of being requested they are pinged on /ping/<name> of the server
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeartbeatOptions {
+    // how often a ping is expected
+    pub period: DurationString,
+    // how late a ping can be before the target fails
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub grace: Option<DurationString>,
+}
+
+impl HeartbeatOptions {
+    // how long to wait for the next ping before failing
+    pub fn deadline(&self) -> Duration {
+        let period: Duration = self.period.into();
+        let grace: Duration = self.grace.map(|g| g.into()).unwrap_or_default();
+        period + grace
+    }
+}
+
+// a target is flapping when its results change between success and failure too often
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FlappingOptions {
+    // number of latest results the rate of changes is calculated over
+    #[serde(
+        default = "FlappingOptions::some_default_window",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub window: Option<u32>,
+    // share of the results in the window that changed, at or above it the target is flapping.
+    // It stops flapping when the share drops below half of it
+    #[serde(
+        default = "FlappingOptions::some_default_ratio",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ratio: Option<f64>,
+}
+
+impl FlappingOptions {
+    fn some_default_window() -> Option<u32> {
+        Some(DEFAULT_FLAPPING_WINDOW)
+    }
+
+    fn some_default_ratio() -> Option<f64> {
+        Some(DEFAULT_FLAPPING_RATIO)
+    }
+
+    pub fn unwrap_window(&self) -> u32 {
+        self.window.expect("failed to get window")
+    }
+
+    pub fn unwrap_ratio(&self) -> f64 {
+        self.ratio.expect("failed to get ratio")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "UPPERCASE")]
+#[strum(serialize_all = "UPPERCASE")]
+pub enum HttpMethod {
+    Get,
+    Head,
+    Post,
+    Put,
+    Patch,
+    Delete,
+    Options,
+}
+
+impl HttpMethod {
+    pub fn to_reqwest_method(&self) -> reqwest::Method {
+        match self {
+            HttpMethod::Get => reqwest::Method::GET,
+            HttpMethod::Head => reqwest::Method::HEAD,
+            HttpMethod::Post => reqwest::Method::POST,
+            HttpMethod::Put => reqwest::Method::PUT,
+            HttpMethod::Patch => reqwest::Method::PATCH,
+            HttpMethod::Delete => reqwest::Method::DELETE,
+            HttpMethod::Options => reqwest::Method::OPTIONS,
+        }
+    }
+}
+
+// the request body is either written directly in the config or read from a file when the requester starts
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestBody {
+    Inline(String),
+    File(String),
+}
+
+impl RequestBody {
+    pub async fn read(&self) -> Result<String, tokio::io::Error> {
+        match self {
+            RequestBody::Inline(body) => Ok(body.clone()),
+            RequestBody::File(path) => read_to_string(path).await,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "lowercase")]
+pub enum Backoff {
+    Fixed,
+    Exponential,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Retry {
+    // number of retries after the first attempt fails
+    pub count: u32,
+    #[serde(default = "Retry::default_backoff")]
+    pub backoff: Backoff,
+    // delay before the first retry. Exponential backoff doubles it for each following retry
+    #[serde(default = "Retry::default_delay")]
+    pub delay: DurationString,
+    // timeout of each attempt, defaults to the timeout of the target
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub attempt_timeout: Option<DurationString>,
+}
+
+impl Retry {
+    fn default_backoff() -> Backoff {
+        Backoff::Fixed
+    }
+
+    fn default_delay() -> DurationString {
+        DurationString::from_string(String::from(DEFAULT_RETRY_DELAY))
+            .expect("failed to create from duration string")
+    }
+
+    // the delay before the nth retry, starting from 1
+    pub fn delay_before(&self, retry: u32) -> Duration {
+        let delay: Duration = self.delay.into();
+        match self.backoff {
+            Backoff::Fixed => delay,
+            Backoff::Exponential => delay * 2u32.saturating_pow(retry.saturating_sub(1)),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LogFile {
     pub file: String,
@@ -46,13 +392,62 @@ impl LogFile {
 pub struct Target {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    // what kind of probe checks the target, defaults to http
+    #[serde(
+        default = "Target::some_default_type",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub r#type: Option<ProbeType>,
+    // not needed for heartbeat targets
+    #[serde(default)]
     pub url: String,
+    // free form labels like "production" or "team-a", sent along with notifications
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    #[serde(
+        default = "Target::some_default_method",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub method: Option<HttpMethod>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub body: Option<RequestBody>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpOptions>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<TcpOptions>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsOptions>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<GrpcOptions>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub websocket: Option<WebsocketOptions>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub heartbeat: Option<HeartbeatOptions>,
     // how often a request should happen
     #[serde(
         default = "Target::some_default_interval",
         skip_serializing_if = "Option::is_none"
     )]
     pub interval: Option<DurationString>,
+    // interval used while the target is failing, so a recovery is noticed sooner
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub interval_on_failure: Option<DurationString>,
+    // consecutive successes before going back from interval_on_failure to the interval
+    #[serde(
+        default = "Target::some_default_interval_recover_after",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub interval_recover_after: Option<u32>,
+    // cron expression used instead of the interval, e.g. "*/5 9-17 * * MON-FRI". Seconds are optional
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    // timezone of the schedule, e.g. Europe/Copenhagen. Defaults to UTC
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<Tz>,
     // if a request hits the timeout it is canceled
     #[serde(
         default = "Target::some_default_timeout",
@@ -65,13 +460,62 @@ pub struct Target {
         skip_serializing_if = "Option::is_none"
     )]
     pub max_concurrent: Option<u32>,
+    // retry a failed request before reporting it as a failure
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub retries: Option<Retry>,
+    // consecutive failures before the target is down, it is degraded until then
+    #[serde(
+        default = "Target::some_default_failure_threshold",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub failure_threshold: Option<u32>,
+    // consecutive successes before a down target is up again
+    #[serde(
+        default = "Target::some_default_recovery_threshold",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub recovery_threshold: Option<u32>,
+    // transitions are not notified while the target is flapping, disabled if not set
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub flapping: Option<FlappingOptions>,
+    // names of targets this one depends on. Its failures are suppressed while one of them is down
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<Vec<String>>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub expect: Option<Expect>,
+    // https targets fail when the certificate expires within this duration
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub cert_warn_before: Option<DurationString>,
     #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
     pub log: Option<LogFile>,
+    // webhooks of this target, in addition to the global ones
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub webhooks: Option<Vec<Webhook>>,
+    // emails of this target, in addition to the global ones
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub emails: Option<Vec<Email>>,
+    // alertmanagers of this target, in addition to the global ones
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub alertmanagers: Option<Vec<Alertmanager>>,
+    // executed when the target goes down, e.g. to restart a service
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub on_down: Option<ExecHook>,
+    // executed when the target is up again after being down
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub on_up: Option<ExecHook>,
     #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
     pub prometheus_response_time_bucket: Option<Vec<f64>>,
 }
 
 impl Target {
+    fn some_default_type() -> Option<ProbeType> {
+        Some(ProbeType::Http)
+    }
+
+    fn some_default_method() -> Option<HttpMethod> {
+        Some(HttpMethod::Get)
+    }
+
     fn some_default_timeout() -> Option<DurationString> {
         Some(
             DurationString::from_string(String::from(DEFAULT_TIMEOUT))
@@ -86,15 +530,35 @@ impl Target {
         )
     }
 
+    fn some_default_interval_recover_after() -> Option<u32> {
+        Some(DEFAULT_INTERVAL_RECOVER_AFTER)
+    }
+
+    fn some_default_failure_threshold() -> Option<u32> {
+        Some(DEFAULT_FAILURE_THRESHOLD)
+    }
+
+    fn some_default_recovery_threshold() -> Option<u32> {
+        Some(DEFAULT_RECOVERY_THRESHOLD)
+    }
+
     fn some_default_max_concurrent() -> Option<u32> {
         Some(DEFAULT_MAX_CONCURRENT)
     }
 
+    // keeps the digits so urls that only differ in an ip or a port get different names
     pub fn normalize_name(name: &String) -> String {
-        name.replace("://", "-")
+        let normalized: String = name
+            .replace("://", "-")
             .chars()
-            .map(|c| if c.is_ascii_alphabetic() { c } else { '_' })
-            .collect()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        // the name is the start of the metric names, which can't start with a digit
+        if normalized.starts_with(|c: char| c.is_ascii_digit()) {
+            format!("_{}", normalized)
+        } else {
+            normalized
+        }
     }
 
     pub fn hydrate(self) -> Self {
@@ -105,13 +569,41 @@ impl Target {
         };
 
         Self {
+            r#type: self.r#type,
             url: self.url,
+            tags: self.tags,
+            severity: self.severity,
+            method: self.method,
+            headers: self.headers,
+            body: self.body,
+            http: self.http,
+            tcp: self.tcp,
+            dns: self.dns,
+            grpc: self.grpc,
+            websocket: self.websocket,
+            heartbeat: self.heartbeat,
             interval: self.interval,
+            interval_on_failure: self.interval_on_failure,
+            interval_recover_after: self.interval_recover_after,
+            schedule: self.schedule,
+            timezone: self.timezone,
             name: Some(name),
             timeout: self.timeout,
             max_concurrent: self.max_concurrent,
+            retries: self.retries,
+            failure_threshold: self.failure_threshold,
+            recovery_threshold: self.recovery_threshold,
+            flapping: self.flapping,
+            depends_on: self.depends_on,
+            expect: self.expect,
+            cert_warn_before: self.cert_warn_before,
             prometheus_response_time_bucket: self.prometheus_response_time_bucket,
             log: self.log,
+            webhooks: self.webhooks,
+            emails: self.emails,
+            alertmanagers: self.alertmanagers,
+            on_down: self.on_down,
+            on_up: self.on_up,
         }
     }
 
@@ -119,10 +611,65 @@ impl Target {
         self.name.clone().expect("failed to get name")
     }
 
+    pub fn clone_unwrap_type(&self) -> ProbeType {
+        self.r#type.clone().expect("failed to get type")
+    }
+
+    // short description of what is probed used in log lines, e.g. "GET https://example.com"
+    pub fn describe(&self) -> String {
+        match self.clone_unwrap_type() {
+            ProbeType::Http => format!("{} {}", self.clone_unwrap_method(), self.url),
+            ProbeType::Tcp => format!("TCP {}", self.url),
+            ProbeType::Dns => format!(
+                "DNS {} {}",
+                self.dns.clone().unwrap_or_default().clone_unwrap_record(),
+                self.url
+            ),
+            ProbeType::Grpc => match self.grpc.as_ref().and_then(|g| g.service.as_ref()) {
+                Some(service) => format!("GRPC {} {}", self.url, service),
+                None => format!("GRPC {}", self.url),
+            },
+            ProbeType::Websocket => format!("WS {}", self.url),
+            ProbeType::Heartbeat => format!("HEARTBEAT {}", self.clone_unwrap_name()),
+        }
+    }
+
+    // the host requests go to, used to rate limit per host
+    pub fn host(&self) -> Option<String> {
+        match self.clone_unwrap_type() {
+            ProbeType::Dns => self.dns.as_ref().and_then(|d| d.resolver.clone()),
+            ProbeType::Heartbeat => None,
+            // urls with a scheme, otherwise host:port addresses
+            _ => reqwest::Url::parse(&self.url)
+                .ok()
+                .and_then(|url| url.host_str().map(|h| h.to_string()))
+                .or_else(|| host_and_port(&self.url, "tcp").ok().map(|(h, _)| h)),
+        }
+    }
+
+    pub fn clone_unwrap_method(&self) -> HttpMethod {
+        self.method.clone().expect("failed to get method")
+    }
+
     pub fn clone_unwrap_interval(&self) -> DurationString {
         self.interval.clone().expect("failed to get timeout")
     }
 
+    pub fn unwrap_interval_recover_after(&self) -> u32 {
+        self.interval_recover_after
+            .expect("failed to get interval_recover_after")
+    }
+
+    pub fn unwrap_failure_threshold(&self) -> u32 {
+        self.failure_threshold
+            .expect("failed to get failure_threshold")
+    }
+
+    pub fn unwrap_recovery_threshold(&self) -> u32 {
+        self.recovery_threshold
+            .expect("failed to get recovery_threshold")
+    }
+
     pub fn clone_unwrap_timeout(&self) -> DurationString {
         self.timeout.clone().expect("failed to get timeout")
     }
@@ -206,22 +753,281 @@ pub struct Config {
     pub grafana: Option<GrafanaConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub targets_defaults: Option<TargetDefault>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<ScheduleConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Vec<MaintenanceWindow>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+    // webhooks notified for all targets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhooks: Option<Vec<Webhook>>,
+    // emails sent for all targets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emails: Option<Vec<Email>>,
+    // alertmanagers alerted for all targets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alertmanagers: Option<Vec<Alertmanager>>,
+    // commands run for all targets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execs: Option<Vec<Exec>>,
+    // pick the global notifiers of a target, all of them are notified when no route matches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routes: Option<Vec<Route>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification_policy: Option<NotificationPolicy>,
     pub targets: Vec<Target>,
 }
 
 impl Config {
+    pub fn schedule_strategy(&self) -> ScheduleStrategy {
+        self.schedule
+            .as_ref()
+            .and_then(|s| s.strategy.clone())
+            .unwrap_or(ScheduleStrategy::Burst)
+    }
+
+    pub fn maintenance_windows(&self) -> Vec<MaintenanceWindow> {
+        self.maintenance.clone().unwrap_or_default()
+    }
+
+    pub fn global_webhooks(&self) -> Vec<Webhook> {
+        self.webhooks.clone().unwrap_or_default()
+    }
+
+    pub fn global_emails(&self) -> Vec<Email> {
+        self.emails.clone().unwrap_or_default()
+    }
+
+    pub fn global_alertmanagers(&self) -> Vec<Alertmanager> {
+        self.alertmanagers.clone().unwrap_or_default()
+    }
+
+    pub fn global_execs(&self) -> Vec<Exec> {
+        self.execs.clone().unwrap_or_default()
+    }
+
+    // names of the global notifiers routes can pick
+    fn receiver_names(&self) -> Vec<String> {
+        let webhooks = self.global_webhooks().into_iter().map(|w| w.name);
+        let emails = self.global_emails().into_iter().map(|e| e.name);
+        let alertmanagers = self.global_alertmanagers().into_iter().map(|a| a.name);
+        let execs = self.global_execs().into_iter().map(|e| e.name);
+        webhooks
+            .chain(emails)
+            .chain(alertmanagers)
+            .chain(execs)
+            .flatten()
+            .collect()
+    }
+
+    // the names of the global notifiers of the target, none when no route matches
+    pub fn route(&self, target: &Target) -> Option<HashSet<String>> {
+        let mut receivers = HashSet::new();
+        let mut matched = false;
+        for route in self.routes.iter().flatten() {
+            if !route.matches(target) {
+                continue;
+            }
+            matched = true;
+            receivers.extend(route.receivers.iter().cloned());
+            if !route.unwrap_continue() {
+                break;
+            }
+        }
+        match matched {
+            true => Some(receivers),
+            false => None,
+        }
+    }
+
+    // checks that routes only pick existing notifiers and that their names are unique
+    pub fn validate_routes(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for name in self.receiver_names() {
+            if !names.insert(name.clone()) {
+                return Err(format!("notifier name {} is used more than once", name));
+            }
+        }
+        for (index, route) in self.routes.iter().flatten().enumerate() {
+            if let Some(receiver) = route.receivers.iter().find(|r| !names.contains(*r)) {
+                return Err(format!(
+                    "route {} has unknown receiver {}",
+                    index + 1,
+                    receiver
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // checks that maintenance windows have a start and end or a schedule and only name existing targets
+    pub fn validate_maintenance(&self) -> Result<(), String> {
+        let names: HashSet<String> = self.targets.iter().map(|t| t.clone_unwrap_name()).collect();
+        for window in self.maintenance_windows() {
+            window.validate()?;
+            if let Some(target) = window
+                .targets
+                .iter()
+                .flatten()
+                .find(|t| !names.contains(*t))
+            {
+                return Err(format!("{} - unknown target {}", window.describe(), target));
+            }
+        }
+        Ok(())
+    }
+
+    // checks that every target has a unique name, which heartbeat targets are pinged by.
+    // Targets without a name are named after their url
+    pub fn validate_targets(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for (index, target) in self.targets.iter().enumerate() {
+            let name = match (&target.name, target.clone_unwrap_type()) {
+                (Some(name), _) => name.clone(),
+                (None, ProbeType::Heartbeat) => {
+                    return Err(format!("heartbeat target {} has no name", index + 1))
+                }
+                (None, _) => Target::normalize_name(&target.url),
+            };
+            if target.clone_unwrap_type() == ProbeType::Heartbeat && target.heartbeat.is_none() {
+                return Err(format!(
+                    "heartbeat target {} has no heartbeat options",
+                    name
+                ));
+            }
+            match (&target.schedule, target.timezone) {
+                (Some(schedule), _) => parse_cron(schedule)
+                    .map(|_| ())
+                    .map_err(|err| format!("target {} has an invalid schedule: {}", name, err))?,
+                (None, Some(_)) => {
+                    return Err(format!("target {} has a timezone but no schedule", name))
+                }
+                (None, None) => (),
+            }
+            if !names.insert(name.clone()) {
+                return Err(match target.name {
+                    Some(_) => format!("target name {} is used more than once", name),
+                    None => format!(
+                        "target {} is named {} like another target, please give it a name",
+                        index + 1,
+                        name
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // names the targets that have no name
+    pub fn hydrate(self) -> Self {
+        Self {
+            targets: self.targets.into_iter().map(Target::hydrate).collect(),
+            ..self
+        }
+    }
+
+    // checks that targets only depend on existing targets and that there are no cycles
+    pub fn validate_dependencies(&self) -> Result<(), String> {
+        let dependencies: HashMap<String, Vec<String>> = self
+            .targets
+            .iter()
+            .map(|t| {
+                (
+                    t.clone_unwrap_name(),
+                    t.depends_on.clone().unwrap_or_default(),
+                )
+            })
+            .collect();
+        for (name, parents) in dependencies.iter() {
+            if let Some(parent) = parents.iter().find(|p| !dependencies.contains_key(*p)) {
+                return Err(format!("{} depends on unknown target {}", name, parent));
+            }
+        }
+        let mut checked = HashSet::new();
+        for name in dependencies.keys() {
+            Self::visit_dependencies(name, &dependencies, &mut Vec::new(), &mut checked)?;
+        }
+        Ok(())
+    }
+
+    // depth first search that fails when a target is reached again from itself
+    fn visit_dependencies(
+        name: &str,
+        dependencies: &HashMap<String, Vec<String>>,
+        path: &mut Vec<String>,
+        checked: &mut HashSet<String>,
+    ) -> Result<(), String> {
+        if let Some(start) = path.iter().position(|p| p == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(format!("dependency cycle {}", cycle.join(" -> ")));
+        }
+        if checked.contains(name) {
+            return Ok(());
+        }
+        path.push(name.to_string());
+        for parent in dependencies.get(name).into_iter().flatten() {
+            Self::visit_dependencies(parent, dependencies, path, checked)?;
+        }
+        path.pop();
+        checked.insert(name.to_string());
+        Ok(())
+    }
+
+    pub fn notification_policy(&self) -> NotificationPolicy {
+        self.notification_policy.clone().unwrap_or_default()
+    }
+
     pub fn create_with_minimal_fields() -> Self {
         Self {
             server: None,
             grafana: None,
             targets_defaults: None,
+            schedule: None,
+            maintenance: None,
+            rate_limit: None,
+            webhooks: None,
+            emails: None,
+            alertmanagers: None,
+            execs: None,
+            routes: None,
+            notification_policy: None,
             targets: vec![Target {
                 name: None,
+                r#type: None,
                 url: String::from("http://example.com"),
+                tags: None,
+                severity: None,
+                method: None,
+                headers: None,
+                body: None,
+                http: None,
+                tcp: None,
+                dns: None,
+                grpc: None,
+                websocket: None,
+                heartbeat: None,
                 interval: None,
+                interval_on_failure: None,
+                interval_recover_after: None,
+                schedule: None,
+                timezone: None,
                 max_concurrent: None,
                 timeout: None,
+                retries: None,
+                failure_threshold: None,
+                recovery_threshold: None,
+                flapping: None,
+                depends_on: None,
+                expect: None,
+                cert_warn_before: None,
                 log: None,
+                webhooks: None,
+                emails: None,
+                alertmanagers: None,
+                on_down: None,
+                on_up: None,
                 prometheus_response_time_bucket: None,
             }
             .hydrate()],
@@ -242,19 +1048,123 @@ impl Config {
             file: "./log/https-example-com.log".to_string(),
             report_on: Some(ReportOn::Success),
         };
-        let url = "https://example.com".to_string();
+        let url = EXAMPLE_URL.to_string();
+        let mut headers = HashMap::new();
+        headers.insert("Accept".to_string(), "text/html".to_string());
+        let expect = Expect {
+            status: Some(vec![
+                StatusCodeMatch::Code(200),
+                StatusCodeMatch::Range(StatusCodeRange { from: 300, to: 399 }),
+            ]),
+            body_contains: Some("Example Domain".to_string()),
+            body_regex: None,
+            json: None,
+            headers: Some(vec![HeaderMatch {
+                name: "Content-Type".to_string(),
+                matches: Some(
+                    Pattern::try_from("^text/html".to_string())
+                        .expect("failed to create header pattern"),
+                ),
+            }]),
+            max_latency: Some(timeout),
+        };
 
         Self {
             server: Some(server),
             grafana: Some(grafana),
             targets_defaults: Some(TargetDefault::default()),
+            schedule: Some(ScheduleConfig {
+                strategy: Some(ScheduleStrategy::Spread),
+            }),
+            maintenance: Some(vec![MaintenanceWindow {
+                name: Some("weekly deploy".to_string()),
+                start: None,
+                end: None,
+                schedule: Some(
+                    CronSchedule::try_from("0 2 * * SUN".to_string())
+                        .expect("failed to create maintenance schedule"),
+                ),
+                duration: Some(
+                    DurationString::from_string("30m".to_string())
+                        .expect("failed to create maintenance duration"),
+                ),
+                timezone: None,
+                // only the example target, a window for all targets would pause them every week
+                targets: Some(vec![Target::normalize_name(&String::from(EXAMPLE_URL))]),
+                action: Some(MaintenanceAction::Pause),
+            }]),
+            rate_limit: Some(RateLimit {
+                max_requests_per_second: Some(50),
+                per_host: Some(HostRateLimit {
+                    max_concurrent: Some(2),
+                    max_requests_per_second: Some(10),
+                }),
+            }),
+            // notifiers are left out, the generated config must not send anything on its own
+            webhooks: None,
+            emails: None,
+            alertmanagers: None,
+            execs: None,
+            routes: None,
+            notification_policy: Some(NotificationPolicy {
+                renotify_after: Some(
+                    DurationString::from_string("1h".to_string())
+                        .expect("failed to create renotify after"),
+                ),
+                escalate_after: Some(
+                    DurationString::from_string("30m".to_string())
+                        .expect("failed to create escalate after"),
+                ),
+            }),
             targets: vec![Target {
                 name: Some(Target::normalize_name(&url)),
+                r#type: Some(ProbeType::Http),
                 url,
+                tags: Some(vec![String::from("production")]),
+                severity: Some(Severity::Critical),
+                method: Some(HttpMethod::Get),
+                headers: Some(headers),
+                body: None,
+                http: None,
+                tcp: None,
+                dns: None,
+                grpc: None,
+                websocket: None,
+                heartbeat: None,
                 interval: Some(interval),
+                interval_on_failure: Some(
+                    DurationString::from_string("10s".to_string())
+                        .expect("failed to create interval on failure"),
+                ),
+                interval_recover_after: Some(DEFAULT_INTERVAL_RECOVER_AFTER),
+                schedule: None,
+                timezone: None,
                 timeout: Some(timeout),
                 max_concurrent: Some(DEFAULT_MAX_CONCURRENT),
+                retries: Some(Retry {
+                    count: 2,
+                    backoff: Backoff::Exponential,
+                    delay: Retry::default_delay(),
+                    attempt_timeout: Some(timeout),
+                }),
+                failure_threshold: Some(DEFAULT_FAILURE_THRESHOLD),
+                recovery_threshold: Some(DEFAULT_RECOVERY_THRESHOLD),
+                flapping: Some(FlappingOptions {
+                    window: Some(DEFAULT_FLAPPING_WINDOW),
+                    ratio: Some(DEFAULT_FLAPPING_RATIO),
+                }),
+                depends_on: None,
+                expect: Some(expect),
+                cert_warn_before: Some(
+                    DurationString::from_string(DEFAULT_CERT_WARN_BEFORE.to_string())
+                        .expect("failed to create cert warn before"),
+                ),
                 log: Some(log),
+                webhooks: None,
+                emails: None,
+                alertmanagers: None,
+                on_down: None,
+                on_up: None,
                 prometheus_response_time_bucket: Some(vec![100.0, 250.0, 500.0, 1000.0]),
             }],
         }
@@ -269,11 +1179,39 @@ impl Config {
             .map(|l| {
                 Target {
                     name: None,
+                    r#type: None,
                     url: l.to_string(),
+                    tags: None,
+                    severity: None,
+                    method: None,
+                    headers: None,
+                    body: None,
+                    http: None,
+                    tcp: None,
+                    dns: None,
+                    grpc: None,
+                    websocket: None,
+                    heartbeat: None,
                     interval: None,
+                    interval_on_failure: None,
+                    interval_recover_after: None,
+                    schedule: None,
+                    timezone: None,
                     max_concurrent: None,
                     timeout: None,
+                    retries: None,
+                    failure_threshold: None,
+                    recovery_threshold: None,
+                    flapping: None,
+                    depends_on: None,
+                    expect: None,
+                    cert_warn_before: None,
                     log: None,
+                    webhooks: None,
+                    emails: None,
+                    alertmanagers: None,
+                    on_down: None,
+                    on_up: None,
                     prometheus_response_time_bucket: None,
                 }
                 .hydrate()
@@ -284,6 +1222,15 @@ impl Config {
             server: None,
             grafana: None,
             targets_defaults: None,
+            schedule: None,
+            maintenance: None,
+            rate_limit: None,
+            webhooks: None,
+            emails: None,
+            alertmanagers: None,
+            execs: None,
+            routes: None,
+            notification_policy: None,
             targets,
         }
     }
@@ -313,11 +1260,42 @@ impl Config {
 
                 Target {
                     name: Some(name),
+                    r#type: Some(ProbeType::Http),
                     url,
+                    tags: Some(vec![String::from("production")]),
+                    severity: Some(Severity::Critical),
+                    method: Some(HttpMethod::Get),
+                    headers: None,
+                    body: None,
+                    http: None,
+                    tcp: None,
+                    dns: None,
+                    grpc: None,
+                    websocket: None,
+                    heartbeat: None,
                     interval: Some(interval),
+                    interval_on_failure: None,
+                    interval_recover_after: Some(DEFAULT_INTERVAL_RECOVER_AFTER),
+                    schedule: None,
+                    timezone: None,
                     max_concurrent: Some(1),
                     timeout: Some(timeout),
+                    retries: None,
+                    failure_threshold: Some(DEFAULT_FAILURE_THRESHOLD),
+                    recovery_threshold: Some(DEFAULT_RECOVERY_THRESHOLD),
+                    flapping: Some(FlappingOptions {
+                        window: Some(DEFAULT_FLAPPING_WINDOW),
+                        ratio: Some(DEFAULT_FLAPPING_RATIO),
+                    }),
+                    depends_on: None,
+                    expect: None,
+                    cert_warn_before: None,
                     log: Some(log),
+                    webhooks: None,
+                    emails: None,
+                    alertmanagers: None,
+                    on_down: None,
+                    on_up: None,
                     prometheus_response_time_bucket: Some(vec![100.0, 250.0, 500.0, 1000.0]),
                 }
                 .hydrate()
@@ -327,7 +1305,398 @@ impl Config {
             server: Some(server),
             grafana: Some(grafana),
             targets_defaults: Some(TargetDefault::default()),
+            schedule: Some(ScheduleConfig {
+                strategy: Some(ScheduleStrategy::Spread),
+            }),
+            maintenance: Some(vec![MaintenanceWindow {
+                name: Some("weekly deploy".to_string()),
+                start: None,
+                end: None,
+                schedule: Some(
+                    CronSchedule::try_from("0 2 * * SUN".to_string())
+                        .expect("failed to create maintenance schedule"),
+                ),
+                duration: Some(
+                    DurationString::from_string("30m".to_string())
+                        .expect("failed to create maintenance duration"),
+                ),
+                timezone: None,
+                // only the example target, a window for all targets would pause them every week
+                targets: Some(vec![Target::normalize_name(&String::from(EXAMPLE_URL))]),
+                action: Some(MaintenanceAction::Pause),
+            }]),
+            rate_limit: Some(RateLimit {
+                max_requests_per_second: Some(50),
+                per_host: Some(HostRateLimit {
+                    max_concurrent: Some(2),
+                    max_requests_per_second: Some(10),
+                }),
+            }),
+            // notifiers are left out, the generated config must not send anything on its own
+            webhooks: None,
+            emails: None,
+            alertmanagers: None,
+            execs: None,
+            routes: None,
+            notification_policy: Some(NotificationPolicy {
+                renotify_after: Some(
+                    DurationString::from_string("1h".to_string())
+                        .expect("failed to create renotify after"),
+                ),
+                escalate_after: Some(
+                    DurationString::from_string("30m".to_string())
+                        .expect("failed to create escalate after"),
+                ),
+            }),
             targets,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(yaml: &str) -> Result<(), String> {
+        serde_yaml::from_str::<Config>(yaml)
+            .unwrap()
+            .validate_targets()
+    }
+
+    #[test]
+    fn generates_an_inert_maximal_config() {
+        let yaml = serde_yaml::to_string(&Config::create_with_maximum_fields()).unwrap();
+        let config = serde_yaml::from_str::<Config>(&yaml).unwrap().hydrate();
+        assert_eq!(config.validate_targets(), Ok(()));
+        assert_eq!(config.validate_routes(), Ok(()));
+        assert!(config.webhooks.is_none() && config.alertmanagers.is_none());
+        assert!(config.targets.iter().all(|t| t.on_down.is_none()));
+        // the maintenance window only pauses the example target
+        assert_eq!(config.validate_maintenance(), Ok(()));
+        assert!(config
+            .maintenance_windows()
+            .iter()
+            .all(|w| w.targets.is_some()));
+    }
+
+    fn maintenance(windows: &str) -> Result<(), String> {
+        let yaml = format!(
+            "targets:
+  - name: api
+    url: http://localhost
+maintenance:
+{}",
+            windows
+        );
+        serde_yaml::from_str::<Config>(&yaml)
+            .map_err(|err| err.to_string())?
+            .hydrate()
+            .validate_maintenance()
+    }
+
+    #[test]
+    fn validates_maintenance_windows() {
+        let window = "  - name: deploy
+    schedule: \"0 2 * * SUN\"
+    duration: 30m
+    targets: [api]";
+        assert_eq!(maintenance(window), Ok(()));
+        let window = "  - name: deploy
+    start: 2020-05-01T22:00:00Z
+    end: 2020-05-01T21:00:00Z";
+        assert_eq!(
+            maintenance(window),
+            Err(String::from("deploy - start must be before end"))
+        );
+        let window = "  - name: deploy
+    schedule: \"0 2 * * SUN\"";
+        assert_eq!(
+            maintenance(window),
+            Err(String::from(
+                "deploy - needs either start and end or schedule and duration"
+            ))
+        );
+        let window = "  - name: deploy
+    schedule: \"0 2 * * SUN\"
+    duration: 30m
+    targets: [api, web]";
+        assert_eq!(
+            maintenance(window),
+            Err(String::from("deploy - unknown target web"))
+        );
+        // an invalid schedule fails when the config is parsed
+        let window = "  - schedule: \"at 2am\"
+    duration: 30m";
+        assert!(maintenance(window)
+            .unwrap_err()
+            .contains("invalid schedule 'at 2am'"));
+    }
+
+    #[test]
+    fn accepts_named_heartbeats_and_unnamed_requests() {
+        let yaml = "targets:
+  - type: heartbeat
+    name: backup
+    heartbeat:
+      period: 1m
+  - url: http://localhost
+  - url: https://localhost";
+        assert_eq!(validate(yaml), Ok(()));
+    }
+
+    #[test]
+    fn rejects_heartbeats_without_name_or_options() {
+        let yaml = "targets:
+  - type: heartbeat
+    heartbeat:
+      period: 1m";
+        assert_eq!(
+            validate(yaml),
+            Err(String::from("heartbeat target 1 has no name"))
+        );
+        let yaml = "targets:
+  - type: heartbeat
+    name: backup";
+        assert_eq!(
+            validate(yaml),
+            Err(String::from(
+                "heartbeat target backup has no heartbeat options"
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_rate_limits_of_zero() {
+        let limit = |yaml: &str| serde_yaml::from_str::<RateLimit>(yaml).unwrap().validate();
+        assert_eq!(limit("max_requests_per_second: 5"), Ok(()));
+        assert!(limit("max_requests_per_second: 0").is_err());
+        assert!(limit("per_host:\n  max_requests_per_second: 0").is_err());
+        assert!(limit("per_host:\n  max_concurrent: 0").is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let yaml = "targets:
+  - name: api
+    url: http://localhost/a
+  - name: api
+    url: http://localhost/b";
+        assert_eq!(
+            validate(yaml),
+            Err(String::from("target name api is used more than once"))
+        );
+        // unnamed targets are named after their url
+        let yaml = "targets:
+  - url: http://localhost
+  - url: http://localhost";
+        assert_eq!(
+            validate(yaml),
+            Err(String::from(
+                "target 2 is named http_localhost like another target, please give it a name"
+            ))
+        );
+        let yaml = "targets:
+  - url: http://10.0.0.1
+  - url: http://10.0.0.2
+  - type: tcp
+    url: 10.0.0.1:6379";
+        assert_eq!(validate(yaml), Ok(()));
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        let yaml = "targets:
+  - name: api
+    url: http://localhost
+    schedule: \"*/5 9-17 * * MON-FRI\"
+    timezone: Europe/Copenhagen";
+        assert_eq!(validate(yaml), Ok(()));
+        let yaml = "targets:
+  - name: api
+    url: http://localhost
+    schedule: \"every minute\"";
+        assert!(validate(yaml)
+            .unwrap_err()
+            .starts_with("target api has an invalid schedule: "));
+        let yaml = "targets:
+  - name: api
+    url: http://localhost
+    timezone: Europe/Copenhagen";
+        assert_eq!(
+            validate(yaml),
+            Err(String::from("target api has a timezone but no schedule"))
+        );
+        let yaml = "targets:
+  - name: api
+    url: http://localhost
+    schedule: \"* * * * *\"
+    timezone: Mars/Olympus";
+        assert!(serde_yaml::from_str::<Config>(yaml).is_err());
+    }
+
+    #[test]
+    fn names_targets_after_their_url() {
+        let name = |url: &str| Target::normalize_name(&String::from(url));
+        assert_eq!(
+            name("https://api.example.com:8443/v1"),
+            "https_api_example_com_8443_v1"
+        );
+        assert_eq!(name("10.0.0.1:6379"), "_10_0_0_1_6379");
+    }
+
+    fn dependencies(yaml: &str) -> Result<(), String> {
+        serde_yaml::from_str::<Config>(yaml)
+            .unwrap()
+            .hydrate()
+            .validate_dependencies()
+    }
+
+    #[test]
+    fn rejects_unknown_parents() {
+        let yaml = "targets:
+  - name: db
+    url: http://localhost/db
+  - name: app
+    url: http://localhost/app
+    depends_on: [db]";
+        assert_eq!(dependencies(yaml), Ok(()));
+        let yaml = "targets:
+  - name: app
+    url: http://localhost/app
+    depends_on: [db]";
+        assert_eq!(
+            dependencies(yaml),
+            Err(String::from("app depends on unknown target db"))
+        );
+    }
+
+    #[test]
+    fn rejects_dependency_cycles() {
+        let yaml = "targets:
+  - name: app
+    url: http://localhost/app
+    depends_on: [app]";
+        assert_eq!(
+            dependencies(yaml),
+            Err(String::from("dependency cycle app -> app"))
+        );
+        let yaml = "targets:
+  - name: a
+    url: http://localhost/a
+    depends_on: [b]
+  - name: b
+    url: http://localhost/b
+    depends_on: [c]
+  - name: c
+    url: http://localhost/c
+    depends_on: [a]";
+        let err = dependencies(yaml).unwrap_err();
+        // the cycle is reported from whichever target the search starts at
+        assert!(["a -> b -> c -> a", "b -> c -> a -> b", "c -> a -> b -> c"]
+            .iter()
+            .any(|cycle| err == format!("dependency cycle {}", cycle)));
+    }
+
+    const ROUTED: &str = "targets:
+  - name: api-eu
+    url: http://localhost/api
+    tags: [production]
+    severity: critical
+  - name: web
+    url: http://localhost/web
+    severity: info
+webhooks:
+  - name: chat
+    url: http://localhost/chat
+  - name: pager
+    url: http://localhost/pager
+execs:
+  - name: log
+    command: logger
+";
+
+    fn routed(routes: &str) -> Config {
+        serde_yaml::from_str::<Config>(&format!("{}routes:\n{}", ROUTED, routes))
+            .unwrap()
+            .hydrate()
+    }
+
+    fn receivers(config: &Config, target: usize) -> Option<Vec<String>> {
+        config.route(&config.targets[target]).map(|receivers| {
+            let mut receivers: Vec<String> = receivers.into_iter().collect();
+            receivers.sort();
+            receivers
+        })
+    }
+
+    #[test]
+    fn routes_to_the_first_matching_route() {
+        let config = routed(
+            "  - severity: [critical]
+    receivers: [pager]
+  - tags: [production]
+    receivers: [chat]
+  - names: [\"web*\"]
+    receivers: [log]",
+        );
+        assert_eq!(receivers(&config, 0), Some(vec![String::from("pager")]));
+        assert_eq!(receivers(&config, 1), Some(vec![String::from("log")]));
+    }
+
+    #[test]
+    fn continues_after_a_route_that_asks_for_it() {
+        let config = routed(
+            "  - severity: [critical]
+    receivers: [pager]
+    continue: true
+  - tags: [production]
+    receivers: [chat]
+  - receivers: [log]",
+        );
+        assert_eq!(
+            receivers(&config, 0),
+            Some(vec![String::from("chat"), String::from("pager")])
+        );
+    }
+
+    #[test]
+    fn routes_nowhere_when_no_route_matches() {
+        let config = routed(
+            "  - tags: [staging]
+    receivers: [chat]",
+        );
+        assert_eq!(receivers(&config, 0), None);
+        let config = serde_yaml::from_str::<Config>(ROUTED).unwrap().hydrate();
+        assert_eq!(receivers(&config, 0), None);
+    }
+
+    #[test]
+    fn rejects_unknown_receivers() {
+        let config = routed(
+            "  - receivers: [chat, log]
+  - receivers: [chat, email]",
+        );
+        assert_eq!(
+            config.validate_routes(),
+            Err(String::from("route 2 has unknown receiver email"))
+        );
+        let config = routed("  - receivers: [chat, pager, log]");
+        assert_eq!(config.validate_routes(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_duplicate_notifier_names() {
+        let yaml = format!(
+            "{}alertmanagers:
+  - name: chat
+    url: http://localhost:9093
+",
+            ROUTED
+        );
+        let config = serde_yaml::from_str::<Config>(&yaml).unwrap();
+        assert_eq!(
+            config.validate_routes(),
+            Err(String::from("notifier name chat is used more than once"))
+        );
+    }
+}
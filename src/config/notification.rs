@@ -0,0 +1,400 @@
+use super::{hook::ExecHook, HttpMethod, Retry, Severity, Target};
+use crate::utils::{factory, pattern};
+use duration_string::DurationString;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use strum_macros::Display;
+
+const DEFAULT_WEBHOOK_TIMEOUT: &str = "5s";
+const DEFAULT_ALERTMANAGER_TIMEOUT: &str = "5s";
+// below the default resolve_timeout of alertmanager, which resolves alerts that are not sent again
+const DEFAULT_ALERTMANAGER_RESEND_INTERVAL: &str = "1m";
+const DEFAULT_EMAIL_TIMEOUT: &str = "10s";
+const DEFAULT_EMAIL_SUBJECT: &str = "[sonar] {{name}} is {{state}}";
+const DEFAULT_EMAIL_BODY: &str =
+    "{{description}} is {{state}} since {{time}}, it was {{previous_state}}.\n\n{{reason}}\n";
+
+// what a notification is sent for
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum NotifyOn {
+    // the target went down
+    Failure,
+    // the target is up again after being down
+    Recovery,
+    // every success and failure
+    Result,
+}
+
+fn some_default_notify_on() -> Option<Vec<NotifyOn>> {
+    Some(vec![NotifyOn::Failure, NotifyOn::Recovery])
+}
+
+// how notifications about a target that stays down are repeated and escalated
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NotificationPolicy {
+    // notify again while the target is still down, only once per incident if not set
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub renotify_after: Option<DurationString>,
+    // notify the escalation notifiers when the target has been down this long
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub escalate_after: Option<DurationString>,
+}
+
+impl NotificationPolicy {
+    pub fn renotify_interval(&self) -> Option<Duration> {
+        self.renotify_after.map(|d| d.into())
+    }
+
+    pub fn escalation_delay(&self) -> Option<Duration> {
+        self.escalate_after.map(|d| d.into())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Webhook {
+    // routes pick the global notifiers by name
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub url: String,
+    #[serde(
+        default = "Webhook::some_default_method",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub method: Option<HttpMethod>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub headers: Option<HashMap<String, String>>,
+    // json body where placeholders like {{name}} and {{reason}} are replaced, a json object with all values if not set
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    #[serde(
+        default = "some_default_notify_on",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub on: Option<Vec<NotifyOn>>,
+    // only notified when an incident is escalated
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub escalation: Option<bool>,
+    #[serde(
+        default = "Webhook::some_default_timeout",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timeout: Option<DurationString>,
+    // redeliver when the webhook fails or responds with a non 2xx status
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub retries: Option<Retry>,
+}
+
+impl Webhook {
+    fn some_default_method() -> Option<HttpMethod> {
+        Some(HttpMethod::Post)
+    }
+
+    fn some_default_timeout() -> Option<DurationString> {
+        Some(
+            DurationString::from_string(String::from(DEFAULT_WEBHOOK_TIMEOUT))
+                .expect("failed to create from duration string"),
+        )
+    }
+
+    pub fn clone_unwrap_method(&self) -> HttpMethod {
+        self.method.clone().expect("failed to get method")
+    }
+
+    pub fn unwrap_timeout(&self) -> Duration {
+        self.timeout.expect("failed to get timeout").into()
+    }
+
+    pub fn notifies_on(&self, on: NotifyOn) -> bool {
+        self.on.as_ref().is_some_and(|o| o.contains(&on))
+    }
+
+    pub fn is_escalation(&self) -> bool {
+        self.escalation.unwrap_or(false)
+    }
+}
+
+// how the connection to the smtp server is secured
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SmtpSecurity {
+    // plain text, e.g. for a local smtp sink
+    None,
+    // upgrades a plain connection with the STARTTLS command
+    Starttls,
+    // implicit tls from the start of the connection
+    Tls,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Email {
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub host: String,
+    // defaults to 25, 587 or 465 depending on the security
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(
+        default = "Email::some_default_security",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub security: Option<SmtpSecurity>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    // placeholders like {{name}} and {{reason}} are replaced in the subject and body
+    #[serde(
+        default = "Email::some_default_subject",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub subject: Option<String>,
+    #[serde(
+        default = "Email::some_default_body",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub body: Option<String>,
+    #[serde(
+        default = "some_default_notify_on",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub on: Option<Vec<NotifyOn>>,
+    // only notified when an incident is escalated
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub escalation: Option<bool>,
+    #[serde(
+        default = "Email::some_default_timeout",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timeout: Option<DurationString>,
+}
+
+impl Email {
+    fn some_default_security() -> Option<SmtpSecurity> {
+        Some(SmtpSecurity::Starttls)
+    }
+
+    fn some_default_subject() -> Option<String> {
+        Some(String::from(DEFAULT_EMAIL_SUBJECT))
+    }
+
+    fn some_default_body() -> Option<String> {
+        Some(String::from(DEFAULT_EMAIL_BODY))
+    }
+
+    fn some_default_timeout() -> Option<DurationString> {
+        Some(
+            DurationString::from_string(String::from(DEFAULT_EMAIL_TIMEOUT))
+                .expect("failed to create from duration string"),
+        )
+    }
+
+    pub fn clone_unwrap_security(&self) -> SmtpSecurity {
+        self.security.clone().expect("failed to get security")
+    }
+
+    pub fn unwrap_port(&self) -> u16 {
+        self.port.unwrap_or(match self.clone_unwrap_security() {
+            SmtpSecurity::None => 25,
+            SmtpSecurity::Starttls => 587,
+            SmtpSecurity::Tls => 465,
+        })
+    }
+
+    pub fn clone_unwrap_subject(&self) -> String {
+        self.subject.clone().expect("failed to get subject")
+    }
+
+    pub fn clone_unwrap_body(&self) -> String {
+        self.body.clone().expect("failed to get body")
+    }
+
+    pub fn unwrap_timeout(&self) -> Duration {
+        self.timeout.expect("failed to get timeout").into()
+    }
+
+    pub fn notifies_on(&self, on: NotifyOn) -> bool {
+        self.on.as_ref().is_some_and(|o| o.contains(&on))
+    }
+
+    pub fn is_escalation(&self) -> bool {
+        self.escalation.unwrap_or(false)
+    }
+}
+
+// pushes alerts to the v2 api of a prometheus alertmanager, which takes care of routing and silences
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Alertmanager {
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // the alerts endpoint, e.g. http://localhost:9093/api/v2/alerts
+    pub url: String,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub headers: Option<HashMap<String, String>>,
+    // added to the labels of every alert
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub labels: Option<HashMap<String, String>>,
+    // a firing alert is sent again at this interval so alertmanager doesn't resolve it
+    #[serde(
+        default = "Alertmanager::some_default_resend_interval",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub resend_interval: Option<DurationString>,
+    #[serde(
+        default = "Alertmanager::some_default_timeout",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timeout: Option<DurationString>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub retries: Option<Retry>,
+}
+
+impl Alertmanager {
+    pub fn some_default_resend_interval() -> Option<DurationString> {
+        Some(
+            DurationString::from_string(String::from(DEFAULT_ALERTMANAGER_RESEND_INTERVAL))
+                .expect("failed to create from duration string"),
+        )
+    }
+
+    pub fn some_default_timeout() -> Option<DurationString> {
+        Some(
+            DurationString::from_string(String::from(DEFAULT_ALERTMANAGER_TIMEOUT))
+                .expect("failed to create from duration string"),
+        )
+    }
+
+    pub fn unwrap_resend_interval(&self) -> Duration {
+        self.resend_interval
+            .expect("failed to get resend interval")
+            .into()
+    }
+
+    pub fn unwrap_timeout(&self) -> Duration {
+        self.timeout.expect("failed to get timeout").into()
+    }
+}
+
+// runs a command for each notification, the values are filled in like for the on_down and on_up hooks
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Exec {
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub hook: ExecHook,
+    #[serde(
+        default = "some_default_notify_on",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub on: Option<Vec<NotifyOn>>,
+    // only notified when an incident is escalated
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub escalation: Option<bool>,
+}
+
+impl Exec {
+    pub fn notifies_on(&self, on: NotifyOn) -> bool {
+        self.on.as_ref().is_some_and(|o| o.contains(&on))
+    }
+
+    pub fn is_escalation(&self) -> bool {
+        self.escalation.unwrap_or(false)
+    }
+}
+
+// picks the global notifiers of the targets it matches. Like alertmanager routes, the first matching
+// route decides unless it continues to the next ones
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    // patterns of target names where * matches anything, e.g. "api-*"
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub names: Option<Vec<String>>,
+    // the target must have all of these tags
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Vec<Severity>>,
+    // names of the global webhooks, emails, alertmanagers and execs that are notified
+    pub receivers: Vec<String>,
+    // also try the routes after this one when it matches
+    #[serde(default = "factory::none", skip_serializing_if = "Option::is_none")]
+    pub r#continue: Option<bool>,
+}
+
+impl Route {
+    // a route without conditions matches every target
+    pub fn matches(&self, target: &Target) -> bool {
+        let name = target.clone_unwrap_name();
+        let names = self
+            .names
+            .as_ref()
+            .is_none_or(|patterns| patterns.iter().any(|p| pattern::matches(p, &name)));
+        let tags = self.tags.as_ref().is_none_or(|tags| {
+            let target_tags = target.tags.clone().unwrap_or_default();
+            tags.iter().all(|t| target_tags.contains(t))
+        });
+        let severity = self.severity.as_ref().is_none_or(|severities| {
+            target
+                .severity
+                .is_some_and(|severity| severities.contains(&severity))
+        });
+        names && tags && severity
+    }
+
+    pub fn unwrap_continue(&self) -> bool {
+        self.r#continue.unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(yaml: &str) -> Target {
+        serde_yaml::from_str::<Target>(yaml).unwrap().hydrate()
+    }
+
+    fn route(yaml: &str) -> Route {
+        serde_yaml::from_str(&format!("receivers: [chat]\n{}", yaml)).unwrap()
+    }
+
+    #[test]
+    fn matches_target_names() {
+        let api = target("name: api-eu\nurl: http://localhost");
+        assert!(route("").matches(&api));
+        assert!(route("names: [web, api-*]").matches(&api));
+        assert!(!route("names: [web, api-us]").matches(&api));
+    }
+
+    #[test]
+    fn matches_targets_with_all_tags() {
+        let api = target("name: api\nurl: http://localhost\ntags: [production, eu]");
+        assert!(route("tags: [production]").matches(&api));
+        assert!(route("tags: [eu, production]").matches(&api));
+        assert!(!route("tags: [production, us]").matches(&api));
+        let untagged = target("name: api\nurl: http://localhost");
+        assert!(!route("tags: [production]").matches(&untagged));
+    }
+
+    #[test]
+    fn matches_severities() {
+        let critical = target("name: api\nurl: http://localhost\nseverity: critical");
+        assert!(route("severity: [warning, critical]").matches(&critical));
+        assert!(!route("severity: [info]").matches(&critical));
+        // a target without a severity doesn't match a route that asks for one
+        let unrated = target("name: api\nurl: http://localhost");
+        assert!(!route("severity: [info]").matches(&unrated));
+    }
+
+    #[test]
+    fn matches_all_conditions() {
+        let api = target("name: api\nurl: http://localhost\ntags: [production]\nseverity: info");
+        assert!(route("names: [api]\ntags: [production]\nseverity: [info]").matches(&api));
+        assert!(!route("names: [api]\ntags: [production]\nseverity: [critical]").matches(&api));
+    }
+}
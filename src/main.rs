@@ -21,7 +21,6 @@ mod utils;
 
 use clap::{App, Arg, ArgMatches, Shell, SubCommand};
 use log::*;
-use reqwest::Client;
 use simplelog::*;
 use std::path::PathBuf;
 use tokio::runtime;
@@ -102,7 +101,7 @@ fn main() {
                 .enable_all()
                 .build()
                 .expect("failed to create runtime")
-                .block_on(command::run::Command::exercute(config_path, Client::new()))
+                .block_on(command::run::Command::exercute(config_path))
                 .expect("failed run 'run' command");
         }
         (command::autocomplete::NAME, Some(sub_matches)) => {
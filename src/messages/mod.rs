@@ -1,20 +1,65 @@
-use crate::config::Target;
+use crate::config::{notification::NotifyOn, Target};
 use chrono::{DateTime, TimeZone, Utc};
+use strum_macros::Display;
 
 type ResponseCode = u16;
+
+// milliseconds spent in each phase of a request
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    pub dns: u128,
+    pub connect: u128,
+    pub tls: u128,
+    // time until the response headers arrived, for websockets the upgrade handshake
+    pub ttfb: u128,
+    // time to read the response body, for websockets the round trip of the sent message
+    pub body: u128,
+}
+
+impl Timings {
+    pub const PHASES: [&'static str; 5] = ["dns", "connect", "tls", "ttfb", "body"];
+
+    // pairs of phase name and milliseconds in the order of PHASES
+    pub fn phases(&self) -> [(&'static str, u128); 5] {
+        [
+            ("dns", self.dns),
+            ("connect", self.connect),
+            ("tls", self.tls),
+            ("ttfb", self.ttfb),
+            ("body", self.body),
+        ]
+    }
+}
 #[derive(Debug, Clone)]
 pub struct Entry {
     pub time: DateTime<Utc>,
-    pub response_code: ResponseCode,
+    // none for probes without a response code, like tcp
+    pub response_code: Option<ResponseCode>,
     pub latency: u128,
+    pub timings: Timings,
+    pub cert_expires: Option<DateTime<Utc>>,
+    // number of attempts it took, 1 unless the target has retries
+    pub attempts: u32,
+    // records returned by dns probes
+    pub answers: Vec<String>,
+    // the target was in a maintenance window
+    pub maintenance: bool,
+    // interval until the next request, none when it is decided by a cron schedule
+    pub interval_ms: Option<u128>,
     pub target: Target,
 }
 
 #[derive(Debug, Clone)]
 pub struct EntryDTO {
     pub timestamp_seconds: i64,
-    pub response_code: ResponseCode,
+    pub response_code: Option<ResponseCode>,
     pub latency: u128,
+    pub timings: Timings,
+    pub cert_expires_timestamp_seconds: Option<i64>,
+    pub attempts: u32,
+    pub answers: Vec<String>,
+    pub maintenance: bool,
+    pub interval_ms: Option<u128>,
     pub target: Target,
 }
 
@@ -22,31 +67,66 @@ impl Entry {
     pub fn new(
         time: DateTime<Utc>,
         latency: u128,
-        response_code: ResponseCode,
+        response_code: Option<ResponseCode>,
+        timings: Timings,
+        cert_expires: Option<DateTime<Utc>>,
+        attempts: u32,
         target: Target,
     ) -> Entry {
         Entry {
             time,
             response_code,
             latency,
+            timings,
+            cert_expires,
+            attempts,
+            answers: Vec::new(),
+            maintenance: false,
+            interval_ms: None,
             target,
         }
     }
 
+    pub fn with_answers(mut self, answers: Vec<String>) -> Entry {
+        self.answers = answers;
+        self
+    }
+
     pub fn from_dto(dto: EntryDTO) -> Entry {
         Entry {
             time: Utc::timestamp(&Utc, dto.timestamp_seconds, 0),
             response_code: dto.response_code,
             latency: dto.latency,
+            timings: dto.timings,
+            cert_expires: dto
+                .cert_expires_timestamp_seconds
+                .map(|t| Utc::timestamp(&Utc, t, 0)),
+            attempts: dto.attempts,
+            answers: dto.answers,
+            maintenance: dto.maintenance,
+            interval_ms: dto.interval_ms,
             target: dto.target,
         }
     }
 
+    // the response code or "-" if the probe has none
+    pub fn response_code_or_dash(&self) -> String {
+        self.response_code
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| String::from("-"))
+    }
+
     pub fn to_dto(&self) -> EntryDTO {
         EntryDTO {
             timestamp_seconds: self.time.timestamp(),
             response_code: self.response_code,
             latency: self.latency,
+            timings: self.timings,
+            cert_expires_timestamp_seconds: self.cert_expires.map(|t| t.timestamp()),
+            attempts: self.attempts,
+            answers: self.answers.clone(),
+            maintenance: self.maintenance,
+            interval_ms: self.interval_ms,
             target: self.target.clone(),
         }
     }
@@ -57,6 +137,14 @@ pub struct Failure {
     pub time: DateTime<Utc>,
     pub latency: u128,
     pub reason: String,
+    pub cert_expires: Option<DateTime<Utc>>,
+    pub attempts: u32,
+    // the target was in a maintenance window
+    pub maintenance: bool,
+    // a target this one depends on was down, so the failure doesn't count
+    pub suppressed: bool,
+    // interval until the next request, none when it is decided by a cron schedule
+    pub interval_ms: Option<u128>,
     pub target: Target,
 }
 
@@ -65,15 +153,32 @@ pub struct FailureDTO {
     pub timestamp_seconds: i64,
     pub latency: u128,
     pub reason: String,
+    pub cert_expires_timestamp_seconds: Option<i64>,
+    pub attempts: u32,
+    pub maintenance: bool,
+    pub suppressed: bool,
+    pub interval_ms: Option<u128>,
     pub target: Target,
 }
 
 impl Failure {
-    pub fn new(time: DateTime<Utc>, latency: u128, reason: String, target: Target) -> Failure {
+    pub fn new(
+        time: DateTime<Utc>,
+        latency: u128,
+        reason: String,
+        cert_expires: Option<DateTime<Utc>>,
+        attempts: u32,
+        target: Target,
+    ) -> Failure {
         Failure {
             time,
             reason,
             latency,
+            cert_expires,
+            attempts,
+            maintenance: false,
+            suppressed: false,
+            interval_ms: None,
             target,
         }
     }
@@ -83,6 +188,13 @@ impl Failure {
             time: Utc::timestamp(&Utc, dto.timestamp_seconds, 0),
             reason: dto.reason,
             latency: dto.latency,
+            cert_expires: dto
+                .cert_expires_timestamp_seconds
+                .map(|t| Utc::timestamp(&Utc, t, 0)),
+            attempts: dto.attempts,
+            maintenance: dto.maintenance,
+            suppressed: dto.suppressed,
+            interval_ms: dto.interval_ms,
             target: dto.target,
         }
     }
@@ -92,7 +204,145 @@ impl Failure {
             timestamp_seconds: self.time.timestamp(),
             reason: self.reason.clone(),
             latency: self.latency,
+            cert_expires_timestamp_seconds: self.cert_expires.map(|t| t.timestamp()),
+            attempts: self.attempts,
+            maintenance: self.maintenance,
+            suppressed: self.suppressed,
+            interval_ms: self.interval_ms,
             target: self.target.clone(),
         }
     }
 }
+
+// the state of a target decided by its consecutive results
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+#[strum(serialize_all = "UPPERCASE")]
+pub enum TargetState {
+    // no results yet
+    Unknown,
+    Up,
+    // failing, but less than failure_threshold times in a row
+    Degraded,
+    Down,
+    // changing between success and failure too often, transitions are not notified
+    Flapping,
+}
+
+// sent when a target moves from one state to another
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    pub time: DateTime<Utc>,
+    pub from: TargetState,
+    pub to: TargetState,
+    // reason of the failure that caused the change, none when the change was caused by a success
+    pub reason: Option<String>,
+    // latency of the result that caused the change
+    pub latency: u128,
+    pub target: Target,
+}
+
+// sent to notifiers, either for a state change or for a single result
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub on: NotifyOn,
+    pub time: DateTime<Utc>,
+    // none for results, they are not tied to a state change
+    pub state: Option<TargetState>,
+    pub previous_state: Option<TargetState>,
+    pub reason: Option<String>,
+    pub latency: Option<u128>,
+    pub response_code: Option<ResponseCode>,
+    // when the incident started, set for failures and recoveries
+    pub down_since: Option<DateTime<Utc>>,
+    // the incident has been escalated
+    pub escalated: bool,
+    // the failure is suppressed because a target this one depends on is down
+    pub suppressed: bool,
+    pub target: Target,
+}
+
+impl Notification {
+    pub fn from_state_change(on: NotifyOn, change: &StateChange) -> Notification {
+        Notification {
+            on,
+            time: change.time,
+            state: Some(change.to),
+            previous_state: Some(change.from),
+            reason: change.reason.clone(),
+            latency: Some(change.latency),
+            response_code: None,
+            down_since: None,
+            escalated: false,
+            suppressed: false,
+            target: change.target.clone(),
+        }
+    }
+
+    pub fn from_result(result: &Result<EntryDTO, FailureDTO>) -> Notification {
+        match result {
+            Ok(entry) => Notification {
+                on: NotifyOn::Result,
+                time: Utc.timestamp(entry.timestamp_seconds, 0),
+                state: None,
+                previous_state: None,
+                reason: None,
+                latency: Some(entry.latency),
+                response_code: entry.response_code,
+                down_since: None,
+                escalated: false,
+                suppressed: false,
+                target: entry.target.clone(),
+            },
+            Err(failure) => Notification {
+                on: NotifyOn::Result,
+                time: Utc.timestamp(failure.timestamp_seconds, 0),
+                state: None,
+                previous_state: None,
+                reason: Some(failure.reason.clone()),
+                latency: Some(failure.latency),
+                response_code: None,
+                down_since: None,
+                escalated: false,
+                suppressed: failure.suppressed,
+                target: failure.target.clone(),
+            },
+        }
+    }
+
+    // values by name used to fill in templates, missing values are empty
+    pub fn values(&self) -> Vec<(&'static str, String)> {
+        let or_empty = |value: Option<String>| value.unwrap_or_default();
+        vec![
+            ("name", self.target.clone_unwrap_name()),
+            ("description", self.target.describe()),
+            ("url", self.target.url.clone()),
+            (
+                "tags",
+                self.target.tags.clone().unwrap_or_default().join(","),
+            ),
+            (
+                "severity",
+                or_empty(self.target.severity.map(|s| s.to_string())),
+            ),
+            ("event", self.on.to_string()),
+            ("time", self.time.to_rfc3339()),
+            ("state", or_empty(self.state.map(|s| s.to_string()))),
+            (
+                "previous_state",
+                or_empty(self.previous_state.map(|s| s.to_string())),
+            ),
+            ("reason", or_empty(self.reason.clone())),
+            ("latency", or_empty(self.latency.map(|l| l.to_string()))),
+            (
+                "response_code",
+                or_empty(self.response_code.map(|c| c.to_string())),
+            ),
+            (
+                "down_since",
+                or_empty(self.down_since.map(|d| d.to_rfc3339())),
+            ),
+            ("escalated", self.escalated.to_string()),
+            ("suppressed", self.suppressed.to_string()),
+        ]
+    }
+}
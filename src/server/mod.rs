@@ -1,4 +1,5 @@
 use crate::config::ServerConfig;
+use crate::tasks::heartbeat::{Heartbeat, HeartbeatSenders};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Error, Response, Server, StatusCode};
 use log::*;
@@ -10,11 +11,33 @@ use tokio::sync::oneshot;
 pub struct SonarServer {
     config: ServerConfig,
     registry: Option<Registry>,
+    heartbeats: HeartbeatSenders,
 }
 
+const PING_PATH_PREFIX: &str = "/ping/";
+
 impl SonarServer {
-    pub fn new(config: ServerConfig, registry: Option<Registry>) -> SonarServer {
-        SonarServer { config, registry }
+    pub fn new(
+        config: ServerConfig,
+        registry: Option<Registry>,
+        heartbeats: HeartbeatSenders,
+    ) -> SonarServer {
+        SonarServer {
+            config,
+            registry,
+            heartbeats,
+        }
+    }
+
+    // parses /ping/<name>, /ping/<name>/start and /ping/<name>/fail
+    fn parse_ping(path: &str) -> Option<(&str, Heartbeat)> {
+        let rest = path.strip_prefix(PING_PATH_PREFIX)?.trim_end_matches('/');
+        match rest.rsplitn(2, '/').collect::<Vec<&str>>().as_slice() {
+            ["start", name] => Some((name, Heartbeat::Start)),
+            ["fail", name] => Some((name, Heartbeat::Fail)),
+            [name] => Some((name, Heartbeat::Success)),
+            _ => None,
+        }
     }
 
     // Returns a pair of (shutdown_signal_sender, graceful_shutdown_complete_sender)
@@ -38,16 +61,30 @@ impl SonarServer {
 
         let server_config = self.config.clone();
         let registry = self.registry.clone();
+        let heartbeats = self.heartbeats.clone();
         let make_service = make_service_fn(move |_| {
             let server_config = server_config.clone();
             let registry = registry.clone();
+            let heartbeats = heartbeats.clone();
             async move {
                 Ok::<_, Error>(service_fn(move |req| {
                     let registry = registry.clone();
                     let server_config = server_config.clone();
+                    let heartbeats = heartbeats.clone();
                     async move {
                         let mut response = Response::new(Body::empty());
 
+                        if let Some((name, heartbeat)) = Self::parse_ping(req.uri().path()) {
+                            match heartbeats.get(name) {
+                                Some(sender) if sender.send(heartbeat).is_ok() => {
+                                    debug!("Handling {:?} ping for {}", heartbeat, name);
+                                    *response.body_mut() = Body::from("OK");
+                                }
+                                _ => *response.status_mut() = StatusCode::NOT_FOUND,
+                            }
+                            return Ok::<_, Error>(response);
+                        }
+
                         if server_config.health_endpoint.is_some() {
                             if req.uri().path()
                                 == server_config
@@ -104,6 +141,9 @@ impl SonarServer {
                     .expect("failed to get health check endpoint path")
             );
         }
+        if !self.heartbeats.is_empty() {
+            info!("Heartbeat endpoint at {}<name>", PING_PATH_PREFIX);
+        }
         if self.config.prometheus_endpoint.is_some() {
             info!(
                 "Metrics endpoint at {}",
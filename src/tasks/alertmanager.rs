@@ -0,0 +1,274 @@
+use crate::{
+    config::notification::{Alertmanager, NotifyOn},
+    messages::Notification,
+    tasks::notifier::{self, Notifier},
+};
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::{json, Map, Value};
+use std::time::Duration;
+
+// name of the alerts sonar pushes, alertmanager routes can match on it
+const ALERT_NAME: &str = "SonarTargetDown";
+
+// pushes an alert to alertmanager while a target is down and resolves it when it recovers
+pub struct AlertmanagerNotifier {
+    alertmanager: Alertmanager,
+    client: reqwest::Client,
+}
+
+impl AlertmanagerNotifier {
+    pub fn new(alertmanager: Alertmanager) -> Self {
+        Self {
+            alertmanager,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // a list with a single alert in the format of POST /api/v2/alerts
+    fn payload(&self, notification: &Notification) -> String {
+        let target = &notification.target;
+        let mut labels = Map::new();
+        // the configured labels can't replace the ones identifying the target
+        for (name, value) in self.alertmanager.labels.iter().flatten() {
+            labels.insert(name.clone(), Value::String(value.clone()));
+        }
+        labels.insert(String::from("alertname"), json!(ALERT_NAME));
+        labels.insert(String::from("name"), json!(target.clone_unwrap_name()));
+        if !target.url.is_empty() {
+            labels.insert(String::from("url"), json!(target.url));
+        }
+        if let Some(tags) = target.tags.as_ref().filter(|t| !t.is_empty()) {
+            labels.insert(String::from("tags"), json!(tags.join(",")));
+        }
+        if let Some(severity) = target.severity {
+            labels.insert(String::from("severity"), json!(severity.to_string()));
+        }
+
+        let mut annotations = Map::new();
+        annotations.insert(
+            String::from("summary"),
+            json!(format!("{} is down", target.clone_unwrap_name())),
+        );
+        annotations.insert(String::from("description"), json!(target.describe()));
+        if let Some(reason) = &notification.reason {
+            annotations.insert(String::from("reason"), json!(reason));
+        }
+        if let Some(latency) = notification.latency {
+            annotations.insert(String::from("latency"), json!(format!("{}ms", latency)));
+        }
+
+        let starts_at = notification.down_since.unwrap_or(notification.time);
+        let mut alert = json!({
+            "labels": labels,
+            "annotations": annotations,
+            "startsAt": starts_at.to_rfc3339(),
+        });
+        // an alert without an end is firing, alertmanager resolves it once the end has passed
+        if notification.on == NotifyOn::Recovery {
+            alert["endsAt"] = json!(notification.time.to_rfc3339());
+        }
+        json!([alert]).to_string()
+    }
+
+    async fn send(&self, body: String, attempt_timeout: Duration) -> Result<(), String> {
+        let mut request = self
+            .client
+            .post(&self.alertmanager.url)
+            .timeout(attempt_timeout)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        for (name, value) in self.alertmanager.headers.iter().flatten() {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(format!("responded with {}", response.status())),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for AlertmanagerNotifier {
+    // alertmanager decides who hears about the alert, so it gets every incident
+    fn notifies_on(&self, on: NotifyOn) -> bool {
+        on == NotifyOn::Failure || on == NotifyOn::Recovery
+    }
+
+    fn is_escalation(&self) -> bool {
+        false
+    }
+
+    fn describe(&self) -> String {
+        format!("alertmanager {}", self.alertmanager.url)
+    }
+
+    fn refresh_interval(&self) -> Option<Duration> {
+        Some(self.alertmanager.unwrap_resend_interval())
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        let body = self.payload(notification);
+        let describe = format!("{} - {}", notification.target.describe(), self.describe());
+        notifier::deliver(
+            &describe,
+            self.alertmanager.retries.as_ref(),
+            self.alertmanager.unwrap_timeout(),
+            |attempt_timeout| self.send(body.clone(), attempt_timeout),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+    use chrono::{TimeZone, Utc};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio::prelude::*;
+
+    fn alertmanager(url: &str, config: &str) -> AlertmanagerNotifier {
+        let yaml = format!("url: {}\n{}", url, config);
+        AlertmanagerNotifier::new(serde_yaml::from_str(&yaml).unwrap())
+    }
+
+    fn notification(on: NotifyOn) -> Notification {
+        let target: Target = serde_yaml::from_str(
+            "name: api\nurl: https://api.example.com\ntags: [production, eu]\nseverity: critical",
+        )
+        .unwrap();
+        Notification {
+            on,
+            time: Utc.ymd(2020, 5, 1).and_hms(12, 30, 0),
+            state: None,
+            previous_state: None,
+            reason: Some(String::from("timeout")),
+            latency: Some(5000),
+            response_code: None,
+            down_since: Some(Utc.ymd(2020, 5, 1).and_hms(12, 0, 0)),
+            escalated: false,
+            suppressed: false,
+            target: target.hydrate(),
+        }
+    }
+
+    fn alert(notifier: &AlertmanagerNotifier, on: NotifyOn) -> Value {
+        let payload: Value = serde_json::from_str(&notifier.payload(&notification(on))).unwrap();
+        assert_eq!(payload.as_array().map(|a| a.len()), Some(1));
+        payload[0].clone()
+    }
+
+    #[test]
+    fn labels_identify_the_target() {
+        let notifier = alertmanager(
+            "http://localhost",
+            "labels:\n  alertname: Other\n  name: other\n  team: backend",
+        );
+        let alert = alert(&notifier, NotifyOn::Failure);
+        assert_eq!(
+            alert["labels"],
+            json!({
+                "alertname": ALERT_NAME,
+                "name": "api",
+                "url": "https://api.example.com",
+                "tags": "production,eu",
+                "severity": "critical",
+                "team": "backend",
+            })
+        );
+        assert_eq!(alert["annotations"]["reason"], "timeout");
+        assert_eq!(alert["annotations"]["latency"], "5000ms");
+    }
+
+    #[test]
+    fn starts_when_the_target_went_down() {
+        let notifier = alertmanager("http://localhost", "");
+        let alert = alert(&notifier, NotifyOn::Failure);
+        assert_eq!(alert["startsAt"], "2020-05-01T12:00:00+00:00");
+        assert!(alert.get("endsAt").is_none());
+    }
+
+    #[test]
+    fn ends_on_recovery() {
+        let notifier = alertmanager("http://localhost", "");
+        let alert = alert(&notifier, NotifyOn::Recovery);
+        assert_eq!(alert["startsAt"], "2020-05-01T12:00:00+00:00");
+        assert_eq!(alert["endsAt"], "2020-05-01T12:30:00+00:00");
+    }
+
+    // answers each request with the next status and keeps the bodies it received
+    async fn stand_in(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://127.0.0.1:{}/api/v2/alerts",
+            listener.local_addr().unwrap().port()
+        );
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let received = bodies.clone();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                // the request is complete once the body has the announced length
+                let body = loop {
+                    let n = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length: usize = text[..end]
+                            .lines()
+                            .map(|l| l.to_lowercase())
+                            .find(|l| l.starts_with("content-length: "))
+                            .map(|l| l["content-length: ".len()..].parse().unwrap())
+                            .unwrap_or(0);
+                        if text.len() >= end + 4 + length {
+                            break text[end + 4..].to_string();
+                        }
+                    }
+                };
+                received.lock().unwrap().push(body);
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, bodies)
+    }
+
+    #[tokio::test]
+    async fn retries_until_accepted() {
+        let (url, bodies) = stand_in(vec![503, 500, 200]).await;
+        let notifier = alertmanager(&url, "retries:\n  count: 2\n  delay: 10ms");
+        notifier
+            .notify(&notification(NotifyOn::Failure))
+            .await
+            .unwrap();
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(bodies.len(), 3);
+        // every attempt sends the same alert
+        assert!(bodies.iter().all(|b| b == &bodies[0]));
+        let payload: Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(payload[0]["labels"]["name"], "api");
+    }
+
+    #[tokio::test]
+    async fn fails_after_the_last_retry() {
+        let (url, bodies) = stand_in(vec![503, 503]).await;
+        let notifier = alertmanager(&url, "retries:\n  count: 1\n  delay: 10ms");
+        let err = notifier
+            .notify(&notification(NotifyOn::Failure))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "responded with 503 Service Unavailable after 2 attempts"
+        );
+        assert_eq!(bodies.lock().unwrap().len(), 2);
+    }
+}
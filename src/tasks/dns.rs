@@ -0,0 +1,559 @@
+use crate::{
+    config::{DnsOptions, DnsRecordType, Target},
+    messages::{Entry, Failure, Timings},
+    tasks::probe::Probe,
+    utils::file::read_to_string,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Instant;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::prelude::*;
+
+const DNS_PORT: u16 = 53;
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const CLASS_IN: u16 = 1;
+const MAX_UDP_SIZE: usize = 4096;
+
+pub struct DnsProbe {
+    name: String,
+    resolver: SocketAddr,
+    record: DnsRecordType,
+    expect: Vec<String>,
+}
+
+impl DnsProbe {
+    pub async fn new(target: &Target) -> Result<Self, String> {
+        let options = target.dns.clone().unwrap_or_default();
+        let resolver = match &options.resolver {
+            Some(resolver) => Self::parse_resolver(resolver)?,
+            None => Self::system_resolver().await?,
+        };
+        let record = options.clone_unwrap_record();
+        let expect = Self::normalize_expect(&options, &record);
+
+        Ok(Self {
+            name: target.url.trim_end_matches('.').to_string(),
+            resolver,
+            record,
+            expect,
+        })
+    }
+
+    fn parse_resolver(resolver: &str) -> Result<SocketAddr, String> {
+        if let Ok(addr) = resolver.parse::<SocketAddr>() {
+            return Ok(addr);
+        }
+        resolver
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .map(|ip| SocketAddr::new(ip, DNS_PORT))
+            .map_err(|_| format!("invalid resolver address: {}", resolver))
+    }
+
+    // the first nameserver of the system
+    async fn system_resolver() -> Result<SocketAddr, String> {
+        let conf = read_to_string(RESOLV_CONF)
+            .await
+            .map_err(|err| format!("failed to read {}: {}", RESOLV_CONF, err))?;
+        conf.lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                match (parts.next(), parts.next()) {
+                    (Some("nameserver"), Some(ip)) => Some(ip),
+                    _ => None,
+                }
+            })
+            .find_map(|ip| Self::parse_resolver(ip).ok())
+            .ok_or_else(|| format!("no nameserver found in {}", RESOLV_CONF))
+    }
+
+    // brings expected values into the form answers are reported in
+    fn normalize_expect(options: &DnsOptions, record: &DnsRecordType) -> Vec<String> {
+        options
+            .expect
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|value| match record {
+                DnsRecordType::A | DnsRecordType::Aaaa => value
+                    .parse::<IpAddr>()
+                    .map(|ip| ip.to_string())
+                    .unwrap_or(value),
+                DnsRecordType::Cname => value.trim_end_matches('.').to_lowercase(),
+                DnsRecordType::Txt => value,
+            })
+            .collect()
+    }
+
+    // resolves the name and verifies the answer set against the expected values
+    async fn check(&self) -> Result<Vec<String>, String> {
+        let id = Utc::now().timestamp_subsec_nanos() as u16;
+        let query = encode_query(id, &self.name, self.record.code())?;
+        let mut response = self.query_udp(&query).await?;
+        if response.len() > 2 && response[2] & 0x02 != 0 {
+            // truncated, the full answer is only available over tcp
+            response = self.query_tcp(&query).await?;
+        }
+        let answers = decode_answers(id, &response, self.record.code())?;
+        if answers.is_empty() {
+            return Err(format!("no {} records for {}", self.record, self.name));
+        }
+        let missing: Vec<&String> = self
+            .expect
+            .iter()
+            .filter(|value| !answers.contains(value))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "expected {} records {:?} got {:?}",
+                self.record, missing, answers
+            ));
+        }
+
+        Ok(answers)
+    }
+
+    async fn query_udp(&self, query: &[u8]) -> Result<Vec<u8>, String> {
+        let local: SocketAddr = match self.resolver {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let mut socket = UdpSocket::bind(local)
+            .await
+            .map_err(|err| format!("failed to bind udp socket: {}", err))?;
+        socket
+            .connect(self.resolver)
+            .await
+            .map_err(|err| format!("failed to connect to resolver {}: {}", self.resolver, err))?;
+        socket
+            .send(query)
+            .await
+            .map_err(|err| format!("failed to send query: {}", err))?;
+        let mut buffer = vec![0; MAX_UDP_SIZE];
+        let n = socket
+            .recv(&mut buffer)
+            .await
+            .map_err(|err| format!("failed to receive response: {}", err))?;
+        buffer.truncate(n);
+
+        Ok(buffer)
+    }
+
+    // dns over tcp prefixes each message with its length
+    async fn query_tcp(&self, query: &[u8]) -> Result<Vec<u8>, String> {
+        let mut stream = TcpStream::connect(self.resolver)
+            .await
+            .map_err(|err| format!("failed to connect to resolver {}: {}", self.resolver, err))?;
+        let mut message = (query.len() as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(query);
+        stream
+            .write_all(&message)
+            .await
+            .map_err(|err| format!("failed to send query: {}", err))?;
+        let mut length = [0; 2];
+        stream
+            .read_exact(&mut length)
+            .await
+            .map_err(|err| format!("failed to receive response: {}", err))?;
+        let mut buffer = vec![0; u16::from_be_bytes(length) as usize];
+        stream
+            .read_exact(&mut buffer)
+            .await
+            .map_err(|err| format!("failed to receive response: {}", err))?;
+
+        Ok(buffer)
+    }
+}
+
+fn encode_query(id: u16, name: &str, record_type: u16) -> Result<Vec<u8>, String> {
+    let mut query = Vec::with_capacity(32 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    // recursion desired
+    query.extend_from_slice(&0x0100u16.to_be_bytes());
+    // one question, no answer, authority or additional records
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid dns name: {}", name));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    Ok(query)
+}
+
+// returns the records of the requested type in the answer section of the response
+fn decode_answers(id: u16, response: &[u8], record_type: u16) -> Result<Vec<String>, String> {
+    let malformed = || String::from("malformed dns response");
+    if response.len() < 12 {
+        return Err(malformed());
+    }
+    if read_u16(response, 0)? != id {
+        return Err(String::from("dns response id does not match query"));
+    }
+    match response[3] & 0x0f {
+        0 => (),
+        1 => return Err(String::from("resolver returned FORMERR")),
+        2 => return Err(String::from("resolver returned SERVFAIL")),
+        3 => return Err(String::from("resolver returned NXDOMAIN")),
+        5 => return Err(String::from("resolver returned REFUSED")),
+        code => return Err(format!("resolver returned rcode {}", code)),
+    }
+    let questions = read_u16(response, 4)?;
+    let answer_count = read_u16(response, 6)?;
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_name(response, offset)? + 4;
+    }
+    let mut answers = Vec::new();
+    for _ in 0..answer_count {
+        offset = skip_name(response, offset)?;
+        let rtype = read_u16(response, offset)?;
+        let length = read_u16(response, offset + 8)? as usize;
+        let start = offset + 10;
+        let data = response.get(start..start + length).ok_or_else(malformed)?;
+        offset = start + length;
+        if rtype != record_type {
+            continue;
+        }
+        let answer = match rtype {
+            1 if length == 4 => Ipv4Addr::new(data[0], data[1], data[2], data[3]).to_string(),
+            28 if length == 16 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                Ipv6Addr::from(octets).to_string()
+            }
+            5 => read_name(response, start)?.to_lowercase(),
+            16 => {
+                let mut text = Vec::new();
+                let mut i = 0;
+                while i < data.len() {
+                    let len = data[i] as usize;
+                    text.extend_from_slice(data.get(i + 1..i + 1 + len).ok_or_else(malformed)?);
+                    i += 1 + len;
+                }
+                String::from_utf8_lossy(&text).to_string()
+            }
+            _ => return Err(malformed()),
+        };
+        answers.push(answer);
+    }
+
+    Ok(answers)
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Result<u16, String> {
+    message
+        .get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| String::from("malformed dns response"))
+}
+
+// returns the offset after the name starting at offset
+fn skip_name(message: &[u8], mut offset: usize) -> Result<usize, String> {
+    loop {
+        let len = *message
+            .get(offset)
+            .ok_or_else(|| String::from("malformed dns response"))?;
+        if len == 0 {
+            return Ok(offset + 1);
+        }
+        if len & 0xc0 == 0xc0 {
+            // a pointer to a name elsewhere in the message ends the name
+            return Ok(offset + 2);
+        }
+        offset += 1 + len as usize;
+    }
+}
+
+// reads a possibly compressed name without the trailing dot
+fn read_name(message: &[u8], mut offset: usize) -> Result<String, String> {
+    let malformed = || String::from("malformed dns response");
+    let mut labels = Vec::new();
+    // bounds the number of pointers followed so a pointer loop can't hang the probe
+    let mut jumps = 0;
+    loop {
+        let len = *message.get(offset).ok_or_else(malformed)? as usize;
+        if len == 0 {
+            break;
+        }
+        if len & 0xc0 == 0xc0 {
+            jumps += 1;
+            if jumps > 16 {
+                return Err(malformed());
+            }
+            offset = ((len & 0x3f) << 8) | *message.get(offset + 1).ok_or_else(malformed)? as usize;
+            continue;
+        }
+        let label = message
+            .get(offset + 1..offset + 1 + len)
+            .ok_or_else(malformed)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        offset += 1 + len;
+    }
+
+    Ok(labels.join("."))
+}
+
+#[async_trait]
+impl Probe for DnsProbe {
+    async fn execute(&self, target: &Target) -> Result<Entry, Failure> {
+        let started = Instant::now();
+        match self.check().await {
+            Ok(answers) => {
+                let latency = started.elapsed().as_millis();
+                let timings = Timings {
+                    dns: latency,
+                    ..Timings::default()
+                };
+                Ok(
+                    Entry::new(Utc::now(), latency, None, timings, None, 1, target.clone())
+                        .with_answers(answers),
+                )
+            }
+            Err(reason) => Err(Failure::new(
+                Utc::now(),
+                started.elapsed().as_millis(),
+                reason,
+                None,
+                1,
+                target.clone(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: u16 = 0x1234;
+
+    // a response to the query with the given rcode and answer records of (type, data)
+    fn response(query: &[u8], rcode: u8, answers: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut message = query.to_vec();
+        message[2] = 0x81;
+        message[3] = 0x80 | rcode;
+        message[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+        for (rtype, data) in answers {
+            // the name points to the one in the question
+            message.extend_from_slice(&[0xc0, 12]);
+            message.extend_from_slice(&rtype.to_be_bytes());
+            message.extend_from_slice(&CLASS_IN.to_be_bytes());
+            message.extend_from_slice(&300u32.to_be_bytes());
+            message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            message.extend_from_slice(data);
+        }
+        message
+    }
+
+    #[test]
+    fn encodes_query() {
+        let query = encode_query(ID, "example.com", 28).unwrap();
+        assert_eq!(&query[..12], &[0x12, 0x34, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&query[12..25], b"\x07example\x03com\x00");
+        assert_eq!(&query[25..], &[0, 28, 0, 1]);
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert!(encode_query(ID, "example..com", 1).is_err());
+        assert!(encode_query(ID, "", 1).is_err());
+        assert!(encode_query(ID, &"a".repeat(64), 1).is_err());
+    }
+
+    #[test]
+    fn decodes_answers_of_the_requested_type() {
+        let query = encode_query(ID, "example.com", 1).unwrap();
+        let cname = b"\x03www\xc0\x0c".to_vec();
+        let message = response(
+            &query,
+            0,
+            &[(5, cname), (1, vec![10, 0, 0, 1]), (1, vec![10, 0, 0, 2])],
+        );
+        assert_eq!(
+            decode_answers(ID, &message, 1).unwrap(),
+            vec!["10.0.0.1", "10.0.0.2"]
+        );
+    }
+
+    #[test]
+    fn decodes_aaaa_cname_and_txt() {
+        let query = encode_query(ID, "example.com", 28).unwrap();
+        let ip: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let message = response(&query, 0, &[(28, ip.octets().to_vec())]);
+        assert_eq!(
+            decode_answers(ID, &message, 28).unwrap(),
+            vec!["2001:db8::1"]
+        );
+
+        let query = encode_query(ID, "example.com", 5).unwrap();
+        let message = response(&query, 0, &[(5, b"\x03WWW\xc0\x0c".to_vec())]);
+        assert_eq!(
+            decode_answers(ID, &message, 5).unwrap(),
+            vec!["www.example.com"]
+        );
+
+        let query = encode_query(ID, "example.com", 16).unwrap();
+        let message = response(&query, 0, &[(16, b"\x05hello\x06 world".to_vec())]);
+        assert_eq!(
+            decode_answers(ID, &message, 16).unwrap(),
+            vec!["hello world"]
+        );
+    }
+
+    #[test]
+    fn reports_errors_of_the_resolver() {
+        let query = encode_query(ID, "example.com", 1).unwrap();
+        let message = response(&query, 3, &[]);
+        assert_eq!(
+            decode_answers(ID, &message, 1).unwrap_err(),
+            "resolver returned NXDOMAIN"
+        );
+        let message = response(&query, 2, &[]);
+        assert_eq!(
+            decode_answers(ID, &message, 1).unwrap_err(),
+            "resolver returned SERVFAIL"
+        );
+        assert_eq!(
+            decode_answers(ID + 1, &response(&query, 0, &[]), 1).unwrap_err(),
+            "dns response id does not match query"
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_responses() {
+        let query = encode_query(ID, "example.com", 1).unwrap();
+        let message = response(&query, 0, &[(1, vec![10, 0, 0, 1])]);
+        assert!(decode_answers(ID, &message[..8], 1).is_err());
+        // every cut inside the answer loses part of it
+        for end in query.len()..message.len() {
+            assert!(
+                decode_answers(ID, &message[..end], 1).is_err(),
+                "cut at {}",
+                end
+            );
+        }
+        // an address with the wrong length
+        let message = response(&query, 0, &[(1, vec![10, 0, 0])]);
+        assert!(decode_answers(ID, &message, 1).is_err());
+        // a txt string longer than the record
+        let query = encode_query(ID, "example.com", 16).unwrap();
+        let message = response(&query, 0, &[(16, b"\x09short".to_vec())]);
+        assert!(decode_answers(ID, &message, 16).is_err());
+    }
+
+    #[test]
+    fn reads_compressed_names() {
+        let message = b"\x07example\x03com\x00\x03www\xc0\x00";
+        assert_eq!(read_name(message, 0).unwrap(), "example.com");
+        assert_eq!(read_name(message, 13).unwrap(), "www.example.com");
+    }
+
+    #[test]
+    fn stops_at_pointer_loops() {
+        // a pointer to itself
+        assert!(read_name(b"\xc0\x00", 0).is_err());
+        // two names pointing to each other
+        assert!(read_name(b"\x01a\xc0\x04\x01b\xc0\x00", 0).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_offsets() {
+        // a pointer past the end of the message
+        assert!(read_name(b"\x01a\xc0\xff", 0).is_err());
+        // a pointer without its second byte
+        assert!(read_name(b"\x01a\xc0", 0).is_err());
+        // a label longer than the message
+        assert!(read_name(b"\x05ab", 0).is_err());
+        // a name without its end
+        assert!(read_name(b"\x01a", 0).is_err());
+        assert!(read_name(b"", 5).is_err());
+        assert!(skip_name(b"\x05ab", 0).is_err());
+    }
+
+    // answers queries for example.com with one record of each type and NXDOMAIN for other names
+    async fn stub_resolver() -> SocketAddr {
+        let mut socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = vec![0; MAX_UDP_SIZE];
+            loop {
+                let (n, from) = socket.recv_from(&mut buffer).await.unwrap();
+                let query = &buffer[..n];
+                let name_end = skip_name(query, 12).unwrap();
+                let rtype = read_u16(query, name_end).unwrap();
+                let message = match read_name(query, 12).unwrap().as_str() {
+                    "example.com" => match rtype {
+                        1 => response(query, 0, &[(1, vec![93, 184, 216, 34])]),
+                        28 => {
+                            let ip: Ipv6Addr = "2606:2800:220:1::1".parse().unwrap();
+                            response(query, 0, &[(28, ip.octets().to_vec())])
+                        }
+                        5 => response(query, 0, &[(5, b"\x04edge\xc0\x0c".to_vec())]),
+                        _ => response(query, 0, &[]),
+                    },
+                    _ => response(query, 3, &[]),
+                };
+                socket.send_to(&message, from).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    fn probe(resolver: SocketAddr, name: &str, record: DnsRecordType, expect: &[&str]) -> DnsProbe {
+        DnsProbe {
+            name: name.to_string(),
+            resolver,
+            record,
+            expect: expect.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_through_the_resolver() {
+        let resolver = stub_resolver().await;
+        let answers = probe(
+            resolver,
+            "example.com",
+            DnsRecordType::A,
+            &["93.184.216.34"],
+        )
+        .check()
+        .await;
+        assert_eq!(answers.unwrap(), vec!["93.184.216.34"]);
+        let answers = probe(resolver, "example.com", DnsRecordType::Aaaa, &[])
+            .check()
+            .await;
+        assert_eq!(answers.unwrap(), vec!["2606:2800:220:1::1"]);
+        let answers = probe(resolver, "example.com", DnsRecordType::Cname, &[])
+            .check()
+            .await;
+        assert_eq!(answers.unwrap(), vec!["edge.example.com"]);
+    }
+
+    #[tokio::test]
+    async fn fails_on_unexpected_or_missing_answers() {
+        let resolver = stub_resolver().await;
+        let result = probe(resolver, "example.com", DnsRecordType::A, &["10.0.0.1"])
+            .check()
+            .await;
+        assert!(result.unwrap_err().starts_with("expected A records"));
+        let result = probe(resolver, "missing.example.com", DnsRecordType::A, &[])
+            .check()
+            .await;
+        assert_eq!(result.unwrap_err(), "resolver returned NXDOMAIN");
+        let result = probe(resolver, "example.com", DnsRecordType::Txt, &[])
+            .check()
+            .await;
+        assert_eq!(result.unwrap_err(), "no TXT records for example.com");
+    }
+}
@@ -0,0 +1,346 @@
+use crate::{
+    config::notification::{Email, NotifyOn, SmtpSecurity},
+    messages::Notification,
+    tasks::notifier::Notifier,
+    utils::template,
+};
+use async_native_tls::TlsConnector;
+use async_trait::async_trait;
+use chrono::Utc;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+// name sonar greets the smtp server with
+const EHLO_NAME: &str = "sonar";
+
+// sends notifications as emails over smtp
+pub struct EmailNotifier {
+    email: Email,
+}
+
+impl EmailNotifier {
+    pub fn new(email: Email) -> Self {
+        Self { email }
+    }
+
+    // the message in the format of the DATA command, headers and a plain text body
+    fn message(&self, notification: &Notification) -> String {
+        let values = notification.values();
+        // header values must stay on a single line
+        let subject = template::render(&self.email.clone_unwrap_subject(), &values)
+            .replace(['\r', '\n'], " ");
+        let body = template::render(&self.email.clone_unwrap_body(), &values);
+        let body = body
+            .lines()
+            // a line starting with a dot is escaped with another, a single dot would end the message
+            .map(|line| {
+                if line.starts_with('.') {
+                    format!(".{}\r\n", line)
+                } else {
+                    format!("{}\r\n", line)
+                }
+            })
+            .collect::<String>();
+        format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}",
+            self.email.from,
+            self.email.to.join(", "),
+            subject,
+            Utc::now().to_rfc2822(),
+            body
+        )
+    }
+
+    async fn send(&self, message: String) -> Result<(), String> {
+        let host = self.email.host.as_str();
+        let stream = TcpStream::connect((host, self.email.unwrap_port()))
+            .await
+            .map_err(|err| format!("smtp connect failed: {}", err))?;
+        match self.email.clone_unwrap_security() {
+            SmtpSecurity::None => {
+                let mut stream = BufReader::new(stream);
+                reply(&mut stream, 220).await?;
+                command(&mut stream, &format!("EHLO {}", EHLO_NAME), 250).await?;
+                self.transaction(stream, message).await
+            }
+            SmtpSecurity::Tls => {
+                let stream = TlsConnector::new()
+                    .connect(host, stream)
+                    .await
+                    .map_err(|err| format!("tls handshake failed: {}", err))?;
+                let mut stream = BufReader::new(stream);
+                reply(&mut stream, 220).await?;
+                command(&mut stream, &format!("EHLO {}", EHLO_NAME), 250).await?;
+                self.transaction(stream, message).await
+            }
+            SmtpSecurity::Starttls => {
+                let mut stream = BufReader::new(stream);
+                reply(&mut stream, 220).await?;
+                command(&mut stream, &format!("EHLO {}", EHLO_NAME), 250).await?;
+                command(&mut stream, "STARTTLS", 220).await?;
+                // the server waits for the handshake, so nothing is left in the buffer
+                let stream = TlsConnector::new()
+                    .connect(host, stream.into_inner())
+                    .await
+                    .map_err(|err| format!("tls handshake failed: {}", err))?;
+                let mut stream = BufReader::new(stream);
+                command(&mut stream, &format!("EHLO {}", EHLO_NAME), 250).await?;
+                self.transaction(stream, message).await
+            }
+        }
+    }
+
+    // authenticates if configured and sends the message to all recipients
+    async fn transaction<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut stream: BufReader<S>,
+        message: String,
+    ) -> Result<(), String> {
+        if let Some(username) = &self.email.username {
+            let password = self.email.password.clone().unwrap_or_default();
+            let credentials = base64::encode(format!("\0{}\0{}", username, password));
+            command(&mut stream, &format!("AUTH PLAIN {}", credentials), 235).await?;
+        }
+        command(
+            &mut stream,
+            &format!("MAIL FROM:<{}>", self.email.from),
+            250,
+        )
+        .await?;
+        for to in self.email.to.iter() {
+            command(&mut stream, &format!("RCPT TO:<{}>", to), 250).await?;
+        }
+        command(&mut stream, "DATA", 354).await?;
+        command(&mut stream, &format!("{}.", message), 250).await?;
+        // the message is accepted, a failing quit doesn't matter
+        let _ = command(&mut stream, "QUIT", 221).await;
+        Ok(())
+    }
+}
+
+// writes a command and reads the reply
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    line: &str,
+    expected: u16,
+) -> Result<String, String> {
+    stream
+        .get_mut()
+        .write_all(format!("{}\r\n", line).as_bytes())
+        .await
+        .map_err(|err| format!("smtp write failed: {}", err))?;
+    reply(stream, expected).await
+}
+
+// reads a reply, which can span multiple lines like "250-first" and "250 last", and checks its code
+async fn reply<S: AsyncBufRead + Unpin>(stream: &mut S, expected: u16) -> Result<String, String> {
+    let mut text = String::new();
+    loop {
+        let mut line = String::new();
+        let read = stream
+            .read_line(&mut line)
+            .await
+            .map_err(|err| format!("smtp read failed: {}", err))?;
+        if read == 0 {
+            return Err(String::from("smtp server closed the connection"));
+        }
+        text.push_str(&line);
+        // the last line has a space after the code
+        if line.len() < 4 || line.as_bytes()[3] != b'-' {
+            break;
+        }
+    }
+    let code: u16 = text
+        .get(0..3)
+        .and_then(|c| c.parse().ok())
+        .ok_or_else(|| format!("invalid smtp reply: {}", text.trim()))?;
+    if code != expected {
+        return Err(format!("smtp server replied: {}", text.trim()));
+    }
+    Ok(text)
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn notifies_on(&self, on: NotifyOn) -> bool {
+        self.email.notifies_on(on)
+    }
+
+    fn is_escalation(&self) -> bool {
+        self.email.is_escalation()
+    }
+
+    fn describe(&self) -> String {
+        format!("email {}", self.email.to.join(", "))
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        let message = self.message(notification);
+        let limit = self.email.unwrap_timeout();
+        timeout(limit, self.send(message))
+            .await
+            .map_err(|_| format!("timed out after {}ms", limit.as_millis()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+    use tokio::net::TcpListener;
+
+    // accepts one session, replies to every command with success and returns the commands and the message
+    async fn smtp_sink() -> (u16, tokio::task::JoinHandle<(Vec<String>, String)>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let session = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut commands = Vec::new();
+            let mut message = String::new();
+            stream.get_mut().write_all(b"220 sink\r\n").await.unwrap();
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let reply: &[u8] = match line.trim_end() {
+                    l if l.starts_with("EHLO") => b"250-sink\r\n250 AUTH PLAIN\r\n",
+                    l if l.starts_with("AUTH") => b"235 ok\r\n",
+                    "DATA" => {
+                        stream.get_mut().write_all(b"354 go on\r\n").await.unwrap();
+                        commands.push(String::from("DATA"));
+                        loop {
+                            let mut line = String::new();
+                            stream.read_line(&mut line).await.unwrap();
+                            if line == ".\r\n" {
+                                break;
+                            }
+                            message.push_str(&line);
+                        }
+                        line = String::from("<message>");
+                        b"250 queued\r\n"
+                    }
+                    "QUIT" => b"221 bye\r\n",
+                    _ => b"250 ok\r\n",
+                };
+                stream.get_mut().write_all(reply).await.unwrap();
+                let quit = line.starts_with("QUIT");
+                commands.push(line.trim_end().to_string());
+                if quit {
+                    break;
+                }
+            }
+            (commands, message)
+        });
+        (port, session)
+    }
+
+    fn notification(reason: &str) -> Notification {
+        let target: Target = serde_yaml::from_str("name: web\nurl: http://localhost").unwrap();
+        Notification {
+            on: NotifyOn::Failure,
+            time: Utc::now(),
+            state: None,
+            previous_state: None,
+            reason: Some(reason.to_string()),
+            latency: None,
+            response_code: None,
+            down_since: None,
+            escalated: false,
+            suppressed: false,
+            target: target.hydrate(),
+        }
+    }
+
+    fn email(port: u16, config: &str) -> Email {
+        serde_yaml::from_str(&format!(
+            "host: 127.0.0.1\nport: {}\nsecurity: none\nfrom: sonar@example.com\nto: [ops@example.com, dev@example.com]\n{}",
+            port, config
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_the_message_to_every_recipient() {
+        let (port, session) = smtp_sink().await;
+        let notifier = EmailNotifier::new(email(
+            port,
+            "username: sonar\npassword: secret\nsubject: \"{{name}} failed\"\nbody: \"{{reason}}\"",
+        ));
+        notifier.notify(&notification("timeout")).await.unwrap();
+        let (commands, message) = session.await.unwrap();
+
+        assert_eq!(
+            commands,
+            vec![
+                "EHLO sonar",
+                "AUTH PLAIN AHNvbmFyAHNlY3JldA==",
+                "MAIL FROM:<sonar@example.com>",
+                "RCPT TO:<ops@example.com>",
+                "RCPT TO:<dev@example.com>",
+                "DATA",
+                "<message>",
+                "QUIT",
+            ]
+        );
+        assert!(message.contains("\r\nSubject: web failed\r\n"));
+        assert!(message.contains("\r\nTo: ops@example.com, dev@example.com\r\n"));
+        assert!(message.ends_with("\r\n\r\ntimeout\r\n"));
+    }
+
+    #[tokio::test]
+    async fn escapes_lines_starting_with_a_dot() {
+        let (port, session) = smtp_sink().await;
+        let notifier = EmailNotifier::new(email(port, "body: \"{{reason}}\""));
+        notifier
+            .notify(&notification(".hidden\n.\nkept\n..two"))
+            .await
+            .unwrap();
+        let (_, message) = session.await.unwrap();
+
+        // a single dot in the body would have ended the message early
+        assert!(message.ends_with("\r\n\r\n..hidden\r\n..\r\nkept\r\n...two\r\n"));
+    }
+
+    #[tokio::test]
+    async fn keeps_the_subject_on_one_line() {
+        let (port, session) = smtp_sink().await;
+        let notifier = EmailNotifier::new(email(
+            port,
+            "subject: \"{{name}}: {{reason}}\"\nbody: see the logs",
+        ));
+        notifier
+            .notify(&notification("down\r\nBcc: attacker@example.com\nX: y"))
+            .await
+            .unwrap();
+        let (_, message) = session.await.unwrap();
+
+        assert!(message.contains("\r\nSubject: web: down  Bcc: attacker@example.com X: y\r\n"));
+        assert!(!message.contains("\r\nBcc:"));
+        assert!(!message.contains("\nX: y"));
+    }
+
+    #[tokio::test]
+    async fn fails_on_a_rejected_command() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.get_mut().write_all(b"220 sink\r\n").await.unwrap();
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            stream
+                .get_mut()
+                .write_all(b"554 no service\r\n")
+                .await
+                .unwrap();
+        });
+        let notifier = EmailNotifier::new(email(port, ""));
+        let err = notifier.notify(&notification("timeout")).await.unwrap_err();
+
+        assert_eq!(err, "smtp server replied: 554 no service");
+    }
+}
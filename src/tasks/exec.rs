@@ -0,0 +1,36 @@
+use crate::{
+    config::notification::{Exec, NotifyOn},
+    messages::Notification,
+    tasks::{hook, notifier::Notifier},
+};
+use async_trait::async_trait;
+
+// runs a command for each notification, e.g. a script paging someone
+pub struct ExecNotifier {
+    exec: Exec,
+}
+
+impl ExecNotifier {
+    pub fn new(exec: Exec) -> Self {
+        Self { exec }
+    }
+}
+
+#[async_trait]
+impl Notifier for ExecNotifier {
+    fn notifies_on(&self, on: NotifyOn) -> bool {
+        self.exec.notifies_on(on)
+    }
+
+    fn is_escalation(&self) -> bool {
+        self.exec.is_escalation()
+    }
+
+    fn describe(&self) -> String {
+        format!("exec {}", self.exec.hook.command)
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        hook::execute("exec", &self.exec.hook, notification).await
+    }
+}
@@ -53,12 +53,26 @@ impl FileReporterTask {
 
                         match log.clone_unwrap_report_on() {
                             ReportOn::Success | ReportOn::Both => {
+                                let timings = entry.timings;
+                                let answers = if entry.answers.is_empty() {
+                                    String::new()
+                                } else {
+                                    format!(" answers:{}", entry.answers.join(","))
+                                };
                                 let line = format!(
-                                    "{} {}ms {} {}\n",
+                                    "{} {}ms {} {} dns:{}ms connect:{}ms tls:{}ms ttfb:{}ms body:{}ms attempts:{}{}{}\n",
                                     entry.time.timestamp(),
                                     entry.latency,
-                                    entry.response_code,
-                                    entry.target.url
+                                    entry.response_code_or_dash(),
+                                    entry.target.describe(),
+                                    timings.dns,
+                                    timings.connect,
+                                    timings.tls,
+                                    timings.ttfb,
+                                    timings.body,
+                                    entry.attempts,
+                                    answers,
+                                    if entry.maintenance { " maintenance" } else { "" }
                                 );
                                 match self.file.write(line.as_bytes()).await {
                                     Ok(_) => (),
@@ -78,10 +92,17 @@ impl FileReporterTask {
                         match log.clone_unwrap_report_on() {
                             ReportOn::Both | ReportOn::Failure => {
                                 let line = format!(
-                                    "{} Failed {}ms {} {}\n",
+                                    "{} Failed {}ms {} attempts:{}{}{} {}\n",
                                     entry.time.timestamp(),
-                                    entry.target.url,
                                     entry.latency,
+                                    entry.target.describe(),
+                                    entry.attempts,
+                                    if entry.maintenance {
+                                        " maintenance"
+                                    } else {
+                                        ""
+                                    },
+                                    if entry.suppressed { " suppressed" } else { "" },
                                     entry.reason.trim()
                                 );
                                 match self.file.write((line).as_bytes()).await {
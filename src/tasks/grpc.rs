@@ -0,0 +1,383 @@
+use crate::{
+    config::Target,
+    messages::{Entry, Failure, Timings},
+    tasks::probe::Probe,
+    utils::net,
+};
+use async_native_tls::TlsConnector;
+use async_trait::async_trait;
+use chrono::Utc;
+use hyper::{body::HttpBody, client::conn::Builder, Body, HeaderMap, Request};
+use log::*;
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::lookup_host;
+
+const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+const STATUS_SERVING: u64 = 1;
+
+pub struct GrpcProbe {
+    host: String,
+    port: u16,
+    service: String,
+    tls: Option<TlsConnector>,
+}
+
+impl GrpcProbe {
+    pub fn new(target: &Target) -> Result<Self, String> {
+        let (host, port) = net::host_and_port(&target.url, "grpc")?;
+        let options = target.grpc.clone().unwrap_or_default();
+        let tls = if options.tls.unwrap_or(false) {
+            // grpc servers only accept http2, which has to be negotiated with alpn
+            let mut builder = native_tls::TlsConnector::builder();
+            builder.request_alpns(&["h2"]);
+            Some(TlsConnector::from(builder))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            host,
+            port,
+            service: options.service.unwrap_or_default(),
+            tls,
+        })
+    }
+
+    // calls the health service and fails unless the status is SERVING
+    async fn check(&self, timings: &mut Timings) -> Result<(), String> {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        let request = Request::post(format!(
+            "{}://{}{}",
+            scheme,
+            net::authority(&self.host, self.port),
+            HEALTH_CHECK_PATH
+        ))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(Body::from(encode_request(&self.service)))
+        .map_err(|err| format!("invalid request: {}", err))?;
+
+        let phase = Instant::now();
+        let addrs: Vec<SocketAddr> = lookup_host((self.host.as_str(), self.port))
+            .await
+            .map_err(|err| format!("dns lookup failed: {}", err))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(format!(
+                "dns lookup for {} returned no addresses",
+                self.host
+            ));
+        }
+        timings.dns = phase.elapsed().as_millis();
+
+        let phase = Instant::now();
+        let stream = net::connect_any(&addrs)
+            .await
+            .map_err(|err| format!("tcp connect failed: {}", err))?;
+        timings.connect = phase.elapsed().as_millis();
+
+        let status = match &self.tls {
+            Some(connector) => {
+                let phase = Instant::now();
+                let stream = connector
+                    .connect(&self.host, stream)
+                    .await
+                    .map_err(|err| format!("tls handshake failed: {}", err))?;
+                timings.tls = phase.elapsed().as_millis();
+                Self::exchange(stream, request, timings).await?
+            }
+            None => Self::exchange(stream, request, timings).await?,
+        };
+        if status != STATUS_SERVING {
+            return Err(serving_status_name(status));
+        }
+
+        Ok(())
+    }
+
+    // sends the health check over http2 and returns the serving status
+    async fn exchange<T>(
+        io: T,
+        request: Request<Body>,
+        timings: &mut Timings,
+    ) -> Result<u64, String>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let phase = Instant::now();
+        let (mut sender, connection) = Builder::new()
+            .http2_only(true)
+            .handshake::<_, Body>(io)
+            .await
+            .map_err(|err| format!("http2 handshake failed: {}", err))?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                debug!("connection closed with error: {}", err);
+            }
+        });
+        let response = sender
+            .send_request(request)
+            .await
+            .map_err(|err| format!("request failed: {}", err))?;
+        timings.ttfb = phase.elapsed().as_millis();
+        if !response.status().is_success() {
+            return Err(format!("unexpected http status {}", response.status()));
+        }
+        // errors without a message are sent as headers only
+        verify_grpc_status(response.headers())?;
+
+        let phase = Instant::now();
+        let mut body = response.into_body();
+        let mut message = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|err| format!("failed to read response body: {}", err))?;
+            message.extend_from_slice(&chunk);
+        }
+        let trailers = body
+            .trailers()
+            .await
+            .map_err(|err| format!("failed to read trailers: {}", err))?;
+        timings.body = phase.elapsed().as_millis();
+        if let Some(trailers) = trailers {
+            verify_grpc_status(&trailers)?;
+        }
+
+        decode_response(&message)
+    }
+}
+
+fn verify_grpc_status(headers: &HeaderMap) -> Result<(), String> {
+    let status = match headers.get("grpc-status") {
+        Some(status) => status.to_str().unwrap_or(""),
+        None => return Ok(()),
+    };
+    if status == "0" {
+        return Ok(());
+    }
+    let message = headers
+        .get("grpc-message")
+        .and_then(|m| m.to_str().ok())
+        .unwrap_or("");
+
+    Err(format!("grpc status {} {}", status, message)
+        .trim()
+        .to_string())
+}
+
+fn serving_status_name(status: u64) -> String {
+    match status {
+        0 => String::from("UNKNOWN"),
+        1 => String::from("SERVING"),
+        2 => String::from("NOT_SERVING"),
+        3 => String::from("SERVICE_UNKNOWN"),
+        _ => format!("unknown serving status {}", status),
+    }
+}
+
+// a length prefixed HealthCheckRequest with the service as field 1
+fn encode_request(service: &str) -> Vec<u8> {
+    let mut message = Vec::new();
+    if !service.is_empty() {
+        message.push(0x0a);
+        encode_varint(service.len() as u64, &mut message);
+        message.extend_from_slice(service.as_bytes());
+    }
+    let mut frame = vec![0];
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    frame
+}
+
+// returns the status, field 1, of a length prefixed HealthCheckResponse
+fn decode_response(frame: &[u8]) -> Result<u64, String> {
+    let malformed = || String::from("malformed health check response");
+    if frame.len() < 5 {
+        return Err(String::from("empty health check response"));
+    }
+    if frame[0] != 0 {
+        return Err(String::from(
+            "compressed health check responses are not supported",
+        ));
+    }
+    let length = u32::from_be_bytes([frame[1], frame[2], frame[3], frame[4]]) as usize;
+    let message = frame
+        .get(5..)
+        .and_then(|rest| rest.get(..length))
+        .ok_or_else(malformed)?;
+
+    // a missing field has the default value, which is UNKNOWN
+    let mut status = 0;
+    let mut offset = 0;
+    while offset < message.len() {
+        let key = decode_varint(message, &mut offset).ok_or_else(malformed)?;
+        match (key >> 3, key & 0x07) {
+            (1, 0) => status = decode_varint(message, &mut offset).ok_or_else(malformed)?,
+            (_, 0) => {
+                decode_varint(message, &mut offset).ok_or_else(malformed)?;
+            }
+            (_, 1) => offset = skip(message, offset, 8).ok_or_else(malformed)?,
+            (_, 2) => {
+                let length = decode_varint(message, &mut offset).ok_or_else(malformed)?;
+                offset = skip(message, offset, length).ok_or_else(malformed)?;
+            }
+            (_, 5) => offset = skip(message, offset, 4).ok_or_else(malformed)?,
+            _ => return Err(malformed()),
+        }
+    }
+
+    Ok(status)
+}
+
+// the offset after a skipped field, none when the field runs past the end of the message
+fn skip(message: &[u8], offset: usize, length: u64) -> Option<usize> {
+    usize::try_from(length)
+        .ok()
+        .and_then(|length| offset.checked_add(length))
+        .filter(|end| *end <= message.len())
+}
+
+fn encode_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn decode_varint(data: &[u8], offset: &mut usize) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[async_trait]
+impl Probe for GrpcProbe {
+    async fn execute(&self, target: &Target) -> Result<Entry, Failure> {
+        let started = Instant::now();
+        let mut timings = Timings::default();
+        match self.check(&mut timings).await {
+            Ok(_) => Ok(Entry::new(
+                Utc::now(),
+                started.elapsed().as_millis(),
+                None,
+                timings,
+                None,
+                1,
+                target.clone(),
+            )),
+            Err(reason) => Err(Failure::new(
+                Utc::now(),
+                started.elapsed().as_millis(),
+                reason,
+                None,
+                1,
+                target.clone(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    // a length prefixed message with the given fields
+    fn frame(message: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(message);
+        frame
+    }
+
+    #[test]
+    fn encodes_requests() {
+        assert_eq!(encode_request(""), vec![0, 0, 0, 0, 0]);
+        assert_eq!(
+            encode_request("api"),
+            vec![0, 0, 0, 0, 5, 0x0a, 3, b'a', b'p', b'i']
+        );
+        // lengths from 128 take two bytes
+        let service = "s".repeat(200);
+        let request = encode_request(&service);
+        assert_eq!(&request[..8], &[0, 0, 0, 0, 203, 0x0a, 0xc8, 0x01]);
+        assert_eq!(&request[8..], service.as_bytes());
+    }
+
+    #[test]
+    fn decodes_the_serving_status() {
+        assert_eq!(decode_response(&frame(&[0x08, 1])), Ok(STATUS_SERVING));
+        assert_eq!(decode_response(&frame(&[0x08, 2])), Ok(2));
+        // a default status is not sent at all
+        assert_eq!(decode_response(&frame(&[])), Ok(0));
+    }
+
+    #[test]
+    fn skips_unknown_fields() {
+        let message = [
+            0x10, 0x96, 0x01, // field 2 varint
+            0x1a, 2, b'h', b'i', // field 3 bytes
+            0x21, 1, 2, 3, 4, 5, 6, 7, 8, // field 4 fixed64
+            0x2d, 1, 2, 3, 4, // field 5 fixed32
+            0x08, 1,
+        ];
+        assert_eq!(decode_response(&frame(&message)), Ok(STATUS_SERVING));
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        assert_eq!(
+            decode_response(&[0, 0, 0]),
+            Err(String::from("empty health check response"))
+        );
+        let truncated = frame(&[0x08, 1]);
+        assert!(decode_response(&truncated[..6]).is_err());
+        // a varint without its last byte
+        assert!(decode_response(&frame(&[0x08, 0x81])).is_err());
+        // fields longer than the message
+        assert!(decode_response(&frame(&[0x1a, 5, b'h'])).is_err());
+        assert!(decode_response(&frame(&[0x21, 1, 2])).is_err());
+        assert!(decode_response(&frame(&[0x2d, 1])).is_err());
+        // a length that overflows the offset
+        let mut huge = vec![0x1a];
+        encode_varint(u64::MAX, &mut huge);
+        assert!(decode_response(&frame(&huge)).is_err());
+        // groups are not used by the health service
+        assert!(decode_response(&frame(&[0x0b])).is_err());
+    }
+
+    #[test]
+    fn rejects_compressed_frames() {
+        let mut compressed = frame(&[0x08, 1]);
+        compressed[0] = 1;
+        assert!(decode_response(&compressed).is_err());
+    }
+
+    #[test]
+    fn verifies_the_grpc_status() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(verify_grpc_status(&headers), Ok(()));
+        headers.insert("grpc-status", HeaderValue::from_static("0"));
+        assert_eq!(verify_grpc_status(&headers), Ok(()));
+        headers.insert("grpc-status", HeaderValue::from_static("12"));
+        assert_eq!(
+            verify_grpc_status(&headers),
+            Err(String::from("grpc status 12"))
+        );
+        headers.insert("grpc-message", HeaderValue::from_static("unimplemented"));
+        assert_eq!(
+            verify_grpc_status(&headers),
+            Err(String::from("grpc status 12 unimplemented"))
+        );
+    }
+}
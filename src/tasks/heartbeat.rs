@@ -0,0 +1,143 @@
+use crate::{
+    config::{
+        maintenance::{self, MaintenanceAction, MaintenanceWindow},
+        Target,
+    },
+    messages::{Entry, EntryDTO, Failure, FailureDTO, Timings},
+    tasks::state::Dependencies,
+};
+use chrono::Utc;
+use log::*;
+use std::collections::HashMap;
+use std::time::Instant;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::delay_until;
+
+// a ping received by the server for a heartbeat target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Heartbeat {
+    // the job started, the time until the next success is reported as latency
+    Start,
+    Success,
+    // the job reported that it failed
+    Fail,
+}
+
+// senders of the heartbeat monitors by target name
+pub type HeartbeatSenders = HashMap<String, mpsc::UnboundedSender<Heartbeat>>;
+
+// turns pings into entries and reports a failure when no ping arrives in time
+pub struct HeartbeatMonitorTask {
+    receiver: mpsc::UnboundedReceiver<Heartbeat>,
+    broadcaster: broadcast::Sender<Result<EntryDTO, FailureDTO>>,
+    maintenance: Vec<MaintenanceWindow>,
+    dependencies: Dependencies,
+}
+
+impl HeartbeatMonitorTask {
+    pub fn new(
+        broadcaster: broadcast::Sender<Result<EntryDTO, FailureDTO>>,
+        maintenance: Vec<MaintenanceWindow>,
+        dependencies: Dependencies,
+    ) -> (Self, mpsc::UnboundedSender<Heartbeat>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (
+            Self {
+                receiver,
+                broadcaster,
+                maintenance,
+                dependencies,
+            },
+            sender,
+        )
+    }
+
+    pub async fn run(mut self, target: Target) {
+        debug!("Starting heartbeat monitor for {}", target.describe());
+        let deadline = match &target.heartbeat {
+            Some(heartbeat) => heartbeat.deadline(),
+            None => {
+                error!("{} - missing heartbeat period", target.describe());
+                return;
+            }
+        };
+        let mut last_seen = Instant::now();
+        let mut started: Option<Instant> = None;
+
+        loop {
+            let missed = delay_until((last_seen + deadline).into());
+            tokio::select! {
+                heartbeat = self.receiver.recv() => match heartbeat {
+                    Some(Heartbeat::Start) => {
+                        debug!("{} - job started", target.describe());
+                        started = Some(Instant::now());
+                    }
+                    Some(Heartbeat::Success) => {
+                        last_seen = Instant::now();
+                        let latency = started.take().map(|s| s.elapsed().as_millis()).unwrap_or(0);
+                        let mut entry = Entry::new(
+                            Utc::now(),
+                            latency,
+                            None,
+                            Timings::default(),
+                            None,
+                            1,
+                            target.clone(),
+                        );
+                        match self.maintenance_action(&target) {
+                            Some(MaintenanceAction::Pause) => continue,
+                            action => entry.maintenance = action.is_some(),
+                        }
+                        info!("-\t{}ms\t{}", latency, target.describe());
+                        let _ = self.broadcaster.send(Ok(entry.to_dto()));
+                    }
+                    Some(Heartbeat::Fail) => {
+                        last_seen = Instant::now();
+                        let latency = started.take().map(|s| s.elapsed().as_millis()).unwrap_or(0);
+                        self.fail(&target, latency, String::from("job reported failure"));
+                    }
+                    None => {
+                        debug!("Stopping heartbeat monitor for {}", target.describe());
+                        return;
+                    }
+                },
+                _ = missed => {
+                    // keeps failing once per deadline until the job pings again
+                    last_seen = Instant::now();
+                    self.fail(
+                        &target,
+                        0,
+                        format!("no heartbeat within {}ms", deadline.as_millis()),
+                    );
+                }
+            }
+        }
+    }
+
+    fn maintenance_action(&self, target: &Target) -> Option<MaintenanceAction> {
+        maintenance::active_action(&self.maintenance, &target.clone_unwrap_name(), Utc::now())
+    }
+
+    fn fail(&self, target: &Target, latency: u128, reason: String) {
+        let mut failure = Failure::new(Utc::now(), latency, reason, None, 1, target.clone());
+        match self.maintenance_action(target) {
+            Some(MaintenanceAction::Pause) => {
+                debug!("{} - paused for maintenance", target.describe());
+                return;
+            }
+            action => failure.maintenance = action.is_some(),
+        }
+        let down_parent = self.dependencies.down_parent();
+        failure.suppressed = down_parent.is_some();
+        info!(
+            "Request failure\t{}ms\t{}\t{}{}",
+            latency,
+            target.describe(),
+            failure.reason,
+            down_parent
+                .map(|p| format!("\tsuppressed, {} is down", p))
+                .unwrap_or_default()
+        );
+        let _ = self.broadcaster.send(Err(failure.to_dto()));
+    }
+}
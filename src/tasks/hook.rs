@@ -0,0 +1,155 @@
+use crate::{
+    config::{hook::ExecHook, notification::NotifyOn, Target},
+    messages::{Notification, StateChange, TargetState},
+    tasks::state::TargetStates,
+    utils::template,
+};
+use log::*;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::sync::broadcast::{self, RecvError};
+use tokio::time::timeout;
+
+// output of a hook beyond this is cut off in the log
+const MAX_LOGGED_OUTPUT: usize = 2000;
+
+// runs the on_down and on_up hooks of a target when its state changes
+pub struct HookTask {
+    state_changes: broadcast::Receiver<StateChange>,
+    states: TargetStates,
+}
+
+impl HookTask {
+    pub fn new(state_changes: broadcast::Receiver<StateChange>, states: TargetStates) -> Self {
+        Self {
+            state_changes,
+            states,
+        }
+    }
+
+    // hooks run one after another so a slow on_down finishes before the on_up starts
+    pub async fn run(mut self, target: Target) {
+        let name = target.clone_unwrap_name();
+        // a target that was down before a config reload runs its on_up hook when it recovers
+        let mut down = self
+            .states
+            .lock()
+            .expect("failed to lock target states")
+            .get(&name)
+            == Some(&TargetState::Down);
+        loop {
+            let change = match self.state_changes.recv().await {
+                Ok(change) if change.target.name.as_ref() == Some(&name) => change,
+                Ok(_) => continue,
+                Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(n)) => {
+                    warn!(
+                        "{} - hook runner is lagging behind with: {}",
+                        target.describe(),
+                        n
+                    );
+                    continue;
+                }
+            };
+            match change.to {
+                TargetState::Down if !down => {
+                    down = true;
+                    if let Some(hook) = &target.on_down {
+                        let notification =
+                            Notification::from_state_change(NotifyOn::Failure, &change);
+                        Self::run_hook("on_down hook", hook, &notification).await;
+                    }
+                }
+                TargetState::Up if down => {
+                    down = false;
+                    if let Some(hook) = &target.on_up {
+                        let notification =
+                            Notification::from_state_change(NotifyOn::Recovery, &change);
+                        Self::run_hook("on_up hook", hook, &notification).await;
+                    }
+                }
+                // flapping hides the transitions, the hooks wait until it settles
+                _ => (),
+            }
+        }
+        debug!("stopped hook runner for {}", target.describe());
+    }
+
+    async fn run_hook(kind: &str, hook: &ExecHook, notification: &Notification) {
+        if let Err(err) = execute(kind, hook, notification).await {
+            warn!(
+                "{} - {} {} {}",
+                notification.target.describe(),
+                kind,
+                hook.command,
+                err
+            );
+        }
+    }
+}
+
+// runs the command with the values filled in. Its exit code and output are logged when it succeeds and part of the error otherwise
+pub async fn execute(
+    kind: &str,
+    hook: &ExecHook,
+    notification: &Notification,
+) -> Result<(), String> {
+    let describe = notification.target.describe();
+    let values = notification.values();
+    let args: Vec<String> = hook
+        .args
+        .iter()
+        .flatten()
+        .map(|arg| template::render(arg, &values))
+        .collect();
+    let mut command = Command::new(&hook.command);
+    command
+        .args(&args)
+        // the values are also available as SONAR_NAME, SONAR_REASON and so on
+        .envs(
+            values
+                .iter()
+                .map(|(name, value)| (format!("SONAR_{}", name.to_uppercase()), value)),
+        )
+        .envs(
+            hook.env
+                .iter()
+                .flatten()
+                .map(|(name, value)| (name, template::render(value, &values))),
+        )
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    info!("{} - running {} {}", describe, kind, hook.command);
+    let limit = hook.unwrap_timeout();
+    let output = match timeout(limit, command.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(err)) => return Err(format!("failed to run: {}", err)),
+        Err(_) => return Err(format!("killed after {}ms", limit.as_millis())),
+    };
+    let exit_code = output
+        .status
+        .code()
+        .map(|c| c.to_string())
+        .unwrap_or_else(|| String::from("none (terminated by a signal)"));
+    let result = format!(
+        "exited with {}\tstdout: {}\tstderr: {}",
+        exit_code,
+        logged_output(&output.stdout),
+        logged_output(&output.stderr)
+    );
+    if !output.status.success() {
+        return Err(result);
+    }
+    info!("{} - {} {} {}", describe, kind, hook.command, result);
+    Ok(())
+}
+
+fn logged_output(output: &[u8]) -> String {
+    let output = String::from_utf8_lossy(output);
+    let output = output.trim();
+    match output.char_indices().nth(MAX_LOGGED_OUTPUT) {
+        Some((end, _)) => format!("{}...", &output[..end]),
+        None => output.to_string(),
+    }
+}
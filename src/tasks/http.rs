@@ -1,96 +1,776 @@
 use crate::{
-    config::Target,
-    messages::{Entry, EntryDTO, Failure, FailureDTO},
+    config::{HttpMethod, HttpOptions, Target},
+    messages::{Entry, Failure, Timings},
+    tasks::probe::Probe,
+    utils::{net, tls},
+};
+use async_native_tls::TlsConnector;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hyper::{
+    body::{Bytes, HttpBody},
+    client::conn::Builder,
+    header::{HOST, LOCATION},
+    Body, HeaderMap, Method, Request, Response, StatusCode,
 };
-use atomic::AtomicU32;
-use chrono::Utc;
-use duration_string::DurationString;
 use log::*;
-use reqwest::Client;
-use std::sync::atomic::{self, Ordering};
-use std::time::Instant;
-use tokio::sync::broadcast;
-
-pub struct IntervalRequesterTask {
-    client: Client,
-    broadcaster: broadcast::Sender<Result<EntryDTO, FailureDTO>>,
+use reqwest::Url;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::lookup_host;
+use tokio::time::timeout;
+
+// how long reading the certificate of a failed handshake may take
+const CERT_EXPIRY_TIMEOUT: Duration = Duration::from_secs(5);
+// bodies are kept up to this size for the expectations, larger ones fail the request
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+// bytes of the first answer kept to tell if it starts with the settings frame of an http2 server
+const SNIFF_SIZE: usize = 9;
+const SETTINGS_FRAME_TYPE: u8 = 0x4;
+
+// a response and the time it took to get it
+struct TimedResponse {
+    status: u16,
+    headers: HeaderMap,
+    body: Bytes,
+    // time until the response headers were received
+    latency: Duration,
+    timings: Timings,
+    cert_expires: Option<DateTime<Utc>>,
 }
 
-impl IntervalRequesterTask {
-    pub fn new(
-        client: Client,
-        broadcaster: broadcast::Sender<Result<EntryDTO, FailureDTO>>,
-    ) -> Self {
-        Self {
-            client: client,
-            broadcaster: broadcaster,
+struct RequestFailure {
+    reason: String,
+    latency: Duration,
+    cert_expires: Option<DateTime<Utc>>,
+}
+
+// redirects followed before a request fails, the same as reqwest
+const MAX_REDIRECTS: usize = 10;
+// headers that are not sent along to another host when following a redirect
+const SENSITIVE_HEADERS: [&str; 5] = [
+    "authorization",
+    "cookie",
+    "cookie2",
+    "proxy-authorization",
+    "www-authenticate",
+];
+
+pub struct HttpProbe {
+    method: HttpMethod,
+    url: Url,
+    body: Option<String>,
+    options: HttpOptions,
+    // requests through a proxy from the environment are sent by reqwest, their phases are not timed
+    proxied: Option<reqwest::Client>,
+}
+
+impl HttpProbe {
+    pub async fn new(target: &Target) -> Result<Self, String> {
+        let method = target.clone_unwrap_method();
+        let url = Url::parse(&target.url).map_err(|err| format!("invalid url: {}", err))?;
+        let body = match &target.body {
+            Some(body) => Some(
+                body.read()
+                    .await
+                    .map_err(|err| format!("failed to read request body: {}", err))?,
+            ),
+            None => None,
+        };
+        let options = target.http.clone().unwrap_or_default();
+        let proxied = match net::proxy_from_env(&url) {
+            Some(proxy) => {
+                info!(
+                    "{} - requests go through the proxy {}, only their total time is measured",
+                    target.describe(),
+                    proxy
+                );
+                let proxy = reqwest::Proxy::all(proxy.as_str())
+                    .map_err(|err| format!("invalid proxy {}: {}", proxy, err))?;
+                let mut builder = reqwest::Client::builder().no_proxy().proxy(proxy);
+                if !options.unwrap_follow_redirects() {
+                    builder = builder.redirect(reqwest::redirect::Policy::none());
+                }
+                if options.unwrap_http2() {
+                    builder = builder.http2_prior_knowledge();
+                }
+                Some(
+                    builder
+                        .build()
+                        .map_err(|err| format!("failed to create client: {}", err))?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            method,
+            url,
+            body,
+            options,
+            proxied,
+        })
+    }
+
+    // sends a single request and verifies the response against the expectations of the target
+    async fn request(&self, target: &Target) -> Result<TimedResponse, RequestFailure> {
+        let res = match &self.proxied {
+            Some(client) => self.send_proxied(client, target).await?,
+            None => self.send(target).await?,
+        };
+        let verified = match &target.expect {
+            Some(expect) => expect.verify(
+                res.status,
+                &res.headers,
+                Some(&String::from_utf8_lossy(&res.body)),
+                res.latency,
+            ),
+            None => Ok(()),
+        }
+        .and_then(|_| Self::verify_certificate(target, res.cert_expires));
+
+        match verified {
+            Ok(_) => Ok(res),
+            Err(reason) => Err(RequestFailure {
+                reason,
+                latency: res.latency,
+                cert_expires: res.cert_expires,
+            }),
         }
     }
 
-    pub async fn run(self, target: Target) {
-        debug!("Starting requester for {}", target.url);
-        let mut interval =
-            tokio::time::interval(DurationString::from(target.clone_unwrap_interval()).into());
-        interval.tick().await;
-        let currently_running = std::sync::Arc::from(AtomicU32::new(0));
+    // sends the request and follows its redirects. The phases of all hops are added up
+    async fn send(&self, target: &Target) -> Result<TimedResponse, RequestFailure> {
+        let started = Instant::now();
+        let mut timings = Timings::default();
+        // only the certificate of the target itself, not of an https url an http target redirects to
+        let mut cert_expires = None;
+        let keep_body = target.expect.as_ref().is_some_and(|e| e.needs_body());
+        let mut method = self.method.to_reqwest_method();
+        let mut body = self.body.clone();
+        let mut headers: Vec<(String, String)> = target
+            .headers
+            .iter()
+            .flatten()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let mut url = self.url.clone();
+        let mut visited = vec![url.clone()];
+        let failed = |reason: String, cert_expires: Option<DateTime<Utc>>| RequestFailure {
+            reason,
+            latency: started.elapsed(),
+            cert_expires,
+        };
 
         loop {
-            if currently_running.load(Ordering::SeqCst) >= target.unwrap_max_concurrent() {
-                warn!("HTTP GET - {} - Responses are not delivered in time for more concurrent requests. Skipping a request", target.url);
-                interval.tick().await;
-                continue;
+            let response = self
+                .send_once(
+                    &method,
+                    &url,
+                    &headers,
+                    body.clone(),
+                    &mut timings,
+                    // the first request goes to the url of the target
+                    if visited.len() == 1 {
+                        Some(&mut cert_expires)
+                    } else {
+                        None
+                    },
+                )
+                .await
+                .map_err(|reason| failed(reason, cert_expires))?;
+            let latency = started.elapsed();
+            let status = response.status();
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|l| l.to_str().ok())
+                .and_then(|l| url.join(l).ok());
+            let next = match location {
+                Some(next) if status.is_redirection() && self.options.unwrap_follow_redirects() => {
+                    next
+                }
+                _ => {
+                    let headers = response.headers().clone();
+                    let phase = Instant::now();
+                    let body = Self::read_body(response.into_body(), keep_body)
+                        .await
+                        .map_err(|reason| failed(reason, cert_expires))?;
+                    timings.body = phase.elapsed().as_millis();
+                    return Ok(TimedResponse {
+                        status: status.as_u16(),
+                        headers,
+                        body,
+                        latency,
+                        timings,
+                        cert_expires,
+                    });
+                }
+            };
+
+            if visited.contains(&next) {
+                return Err(failed(format!("redirect loop at {}", next), cert_expires));
             }
-            currently_running.fetch_add(1, Ordering::SeqCst);
-
-            let client = self.client.clone();
-            let sender = self.broadcaster.clone();
-            let target = target.clone();
-            let currently_running = currently_running.clone();
-
-            let req = client
-                .get(&target.url)
-                .timeout(DurationString::from(target.clone_unwrap_timeout()).into());
-
-            let latency = Instant::now();
-            let task = async move {
-                debug!("Sending GET {}", target.url.clone());
-                match req.send().await {
-                    Ok(res) => {
-                        let latency_millis = latency.elapsed().as_millis();
-                        let response_code = res.status().as_u16();
-                        info!(
-                            "{}\t{}ms\t{}",
-                            response_code,
-                            latency_millis,
-                            target.url.clone()
-                        );
-                        let message =
-                            Entry::new(Utc::now(), latency_millis, response_code, target.clone());
-                        let _ = sender.send(Ok(message.to_dto()));
-                    }
-                    Err(err) => {
-                        let latency_millis = latency.elapsed().as_millis();
-
-                        let message = Failure::new(
-                            Utc::now(),
-                            latency_millis,
-                            err.to_string(),
-                            target.clone(),
-                        );
-                        info!(
-                            "Request failure\t{}ms\t{}\t{}",
-                            latency_millis,
-                            target.url.clone(),
-                            err.to_string()
-                        );
-                        let _ = sender.send(Err(message.to_dto()));
+            if visited.len() > MAX_REDIRECTS {
+                return Err(failed(
+                    format!("more than {} redirects", MAX_REDIRECTS),
+                    cert_expires,
+                ));
+            }
+            match status {
+                StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER => {
+                    body = None;
+                    if method != Method::HEAD {
+                        method = Method::GET;
                     }
                 }
-                currently_running.fetch_sub(1, Ordering::SeqCst);
+                // 307 and 308 repeat the request as it was
+                _ => (),
+            }
+            if next.host_str() != url.host_str()
+                || next.port_or_known_default() != url.port_or_known_default()
+            {
+                headers.retain(|(name, _)| {
+                    let name = name.to_lowercase();
+                    name != "host" && !SENSITIVE_HEADERS.contains(&name.as_str())
+                });
+            }
+            debug!("{} - redirected to {}", target.describe(), next);
+            visited.push(next.clone());
+            url = next;
+        }
+    }
+
+    // sends the request on a new connection and times each phase of it until the response headers arrive
+    async fn send_once(
+        &self,
+        method: &Method,
+        url: &Url,
+        headers: &[(String, String)],
+        body: Option<String>,
+        timings: &mut Timings,
+        cert_expires: Option<&mut Option<DateTime<Utc>>>,
+    ) -> Result<Response<Body>, String> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| String::from("url has no host"))?;
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| String::from("url has no port"))?;
+        // ip hosts are used as they are, without the brackets of ipv6
+        let name = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let ip = name.parse::<IpAddr>().ok();
+
+        let mut request = Request::builder()
+            .method(method.clone())
+            .uri(match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_string(),
+            });
+        // a configured host header replaces the one of the url
+        if !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("host"))
+        {
+            let authority = match url.port() {
+                Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+                None => url.host_str().unwrap_or_default().to_string(),
             };
-            tokio::spawn(task);
+            request = request.header(HOST, authority);
+        }
+        for (name, value) in headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let request = request
+            .body(body.map(Body::from).unwrap_or_else(Body::empty))
+            .map_err(|err| format!("invalid request: {}", err))?;
+
+        let addrs: Vec<SocketAddr> = match ip {
+            Some(ip) => vec![SocketAddr::new(ip, port)],
+            None => {
+                let phase = Instant::now();
+                let addrs: Vec<SocketAddr> = lookup_host((name.as_str(), port))
+                    .await
+                    .map_err(|err| format!("dns lookup failed: {}", err))?
+                    .collect();
+                timings.dns += phase.elapsed().as_millis();
+                addrs
+            }
+        };
+        if addrs.is_empty() {
+            return Err(format!("dns lookup for {} returned no addresses", name));
+        }
+
+        let phase = Instant::now();
+        let stream = net::connect_any(&addrs)
+            .await
+            .map_err(|err| format!("tcp connect failed: {}", err))?;
+        timings.connect += phase.elapsed().as_millis();
+
+        let http2 = self.options.unwrap_http2();
+        if url.scheme() != "https" {
+            return Self::exchange(stream, request, http2, timings).await;
+        }
+        let phase = Instant::now();
+        let mut tls = native_tls::TlsConnector::builder();
+        if http2 {
+            tls.request_alpns(&["h2"]);
+        }
+        let stream = match TlsConnector::from(tls).connect(name.as_str(), stream).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                // the certificate may be the reason, its expiry is read on a connection that accepts it
+                if let Some(cert_expires) = cert_expires {
+                    *cert_expires = Self::certificate_expiry(url, &name, port).await;
+                }
+                return Err(format!("tls handshake failed: {}", err));
+            }
+        };
+        timings.tls += phase.elapsed().as_millis();
+        if let Some(cert_expires) = cert_expires {
+            *cert_expires = match stream.peer_certificate() {
+                Ok(Some(certificate)) => tls::certificate_expiry(&certificate)
+                    .map_err(|err| warn!("{} - {}", url, err))
+                    .ok(),
+                _ => None,
+            };
+        }
+        Self::exchange(stream, request, http2, timings).await
+    }
+
+    async fn exchange<T>(
+        io: T,
+        request: Request<Body>,
+        http2: bool,
+        timings: &mut Timings,
+    ) -> Result<Response<Body>, String>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let phase = Instant::now();
+        let io = Sniffed::new(io);
+        let first_bytes = io.first.clone();
+        // an http2 server answers the preface with its settings, an http/1 server with an error or by closing
+        let reason = |err: hyper::Error, what: &str| {
+            let first_bytes = first_bytes.lock().expect("failed to lock first bytes");
+            if http2 && first_bytes.get(3) != Some(&SETTINGS_FRAME_TYPE) {
+                String::from("server does not support h2")
+            } else {
+                format!("{}: {}", what, err)
+            }
+        };
+        let (mut sender, connection) = Builder::new()
+            .http2_only(http2)
+            .handshake(io)
+            .await
+            .map_err(|err| reason(err, "http handshake failed"))?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                debug!("connection closed with error: {}", err);
+            }
+        });
+        let response = sender
+            .send_request(request)
+            .await
+            .map_err(|err| reason(err, "request failed"))?;
+        timings.ttfb += phase.elapsed().as_millis();
+        Ok(response)
+    }
+
+    // keeps the body when the expectations need it, otherwise it is only read to time it
+    async fn read_body(mut body: Body, keep: bool) -> Result<Bytes, String> {
+        let mut kept = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|err| format!("failed to read response body: {}", err))?;
+            if keep {
+                Self::keep_chunk(&mut kept, &chunk)?;
+            }
+        }
+        Ok(Bytes::from(kept))
+    }
+
+    fn keep_chunk(kept: &mut Vec<u8>, chunk: &[u8]) -> Result<(), String> {
+        if kept.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(format!(
+                "response body is larger than {} bytes",
+                MAX_BODY_SIZE
+            ));
+        }
+        kept.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    // sends the request through the proxy, only the time until the headers and the body are measured
+    async fn send_proxied(
+        &self,
+        client: &reqwest::Client,
+        target: &Target,
+    ) -> Result<TimedResponse, RequestFailure> {
+        let started = Instant::now();
+        let failed = |reason: String| RequestFailure {
+            reason,
+            latency: started.elapsed(),
+            cert_expires: None,
+        };
+        let mut request = client.request(self.method.to_reqwest_method(), self.url.clone());
+        for (name, value) in target.headers.iter().flatten() {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some(body) = &self.body {
+            request = request.body(body.clone());
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| failed(format!("request failed: {}", err)))?;
+        let latency = started.elapsed();
+        let mut timings = Timings {
+            ttfb: latency.as_millis(),
+            ..Timings::default()
+        };
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        let phase = Instant::now();
+        let keep_body = target.expect.as_ref().is_some_and(|e| e.needs_body());
+        let mut response = response;
+        let mut kept = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| failed(format!("failed to read response body: {}", err)))?
+        {
+            if keep_body {
+                Self::keep_chunk(&mut kept, &chunk).map_err(failed)?;
+            }
+        }
+        let body = Bytes::from(kept);
+        timings.body = phase.elapsed().as_millis();
+
+        Ok(TimedResponse {
+            status,
+            headers,
+            body,
+            latency,
+            timings,
+            cert_expires: None,
+        })
+    }
 
-            interval.tick().await;
+    // reads when the certificate expires on a second connection that accepts invalid certificates
+    async fn certificate_expiry(url: &Url, host: &str, port: u16) -> Option<DateTime<Utc>> {
+        match timeout(
+            CERT_EXPIRY_TIMEOUT,
+            tls::peer_certificate_expiry(host, port),
+        )
+        .await
+        {
+            Ok(Ok(expires)) => Some(expires),
+            Ok(Err(err)) => {
+                warn!("{} - failed to get certificate expiry: {}", url, err);
+                None
+            }
+            Err(_) => {
+                warn!("{} - timed out getting certificate expiry", url);
+                None
+            }
+        }
+    }
+
+    fn verify_certificate(
+        target: &Target,
+        cert_expires: Option<DateTime<Utc>>,
+    ) -> Result<(), String> {
+        let (warn_before, expires) = match (target.cert_warn_before, cert_expires) {
+            (Some(warn_before), Some(expires)) => (warn_before, expires),
+            _ => return Ok(()),
+        };
+        let warn_before = chrono::Duration::from_std(warn_before.into())
+            .map_err(|err| format!("invalid cert_warn_before: {}", err))?;
+        let remaining = expires - Utc::now();
+        if remaining < warn_before {
+            return Err(format!(
+                "certificate expires in {} days at {}",
+                remaining.num_days(),
+                expires.to_rfc3339()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// an io that keeps the first bytes read from it
+struct Sniffed<T> {
+    io: T,
+    first: Arc<Mutex<Vec<u8>>>,
+}
+
+impl<T> Sniffed<T> {
+    fn new(io: T) -> Self {
+        Self {
+            io,
+            first: Arc::new(Mutex::new(Vec::with_capacity(SNIFF_SIZE))),
         }
     }
 }
+
+impl<T: AsyncRead + Unpin> AsyncRead for Sniffed<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.io).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = &poll {
+            let mut first = self.first.lock().expect("failed to lock first bytes");
+            let missing = SNIFF_SIZE.saturating_sub(first.len()).min(*n);
+            first.extend_from_slice(&buf[..missing]);
+        }
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Sniffed<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl Probe for HttpProbe {
+    async fn execute(&self, target: &Target) -> Result<Entry, Failure> {
+        match self.request(target).await {
+            Ok(res) => Ok(Entry::new(
+                Utc::now(),
+                res.latency.as_millis(),
+                Some(res.status),
+                res.timings,
+                res.cert_expires,
+                1,
+                target.clone(),
+            )),
+            Err(failure) => Err(Failure::new(
+                Utc::now(),
+                failure.latency.as_millis(),
+                failure.reason,
+                failure.cert_expires,
+                1,
+                target.clone(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use hyper::{header, Request, StatusCode};
+    use std::convert::Infallible;
+    use tokio::net::TcpListener;
+
+    fn target(url: &str, options: &str) -> Target {
+        let yaml = format!("url: {}\n{}", url, options);
+        serde_yaml::from_str::<Target>(&yaml).unwrap().hydrate()
+    }
+
+    async fn probe(target: &Target) -> Result<Entry, Failure> {
+        HttpProbe::new(target).await.unwrap().execute(target).await
+    }
+
+    // answers by path, redirects to the https stand-in on /secure
+    async fn respond(request: Request<Body>, secure: u16) -> Result<Response<Body>, Infallible> {
+        let redirect = |status: StatusCode, location: String| {
+            Response::builder()
+                .status(status)
+                .header(header::LOCATION, location)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = match request.uri().path() {
+            "/ok" => Response::builder()
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"status":"up","version":2}"#))
+                .unwrap(),
+            "/teapot" => Response::builder()
+                .status(StatusCode::IM_A_TEAPOT)
+                .body(Body::empty())
+                .unwrap(),
+            "/first" => redirect(StatusCode::MOVED_PERMANENTLY, String::from("/second")),
+            "/second" => redirect(StatusCode::FOUND, String::from("/ok")),
+            "/loop" => redirect(StatusCode::FOUND, String::from("/loop")),
+            "/secure" => redirect(
+                StatusCode::FOUND,
+                format!("https://127.0.0.1:{}/ok", secure),
+            ),
+            "/large" => Response::new(Body::from(vec![b'a'; MAX_BODY_SIZE + 1])),
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap(),
+        };
+        Ok(response)
+    }
+
+    async fn server(http2: bool, secure: u16) -> u16 {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = service_fn(move |request| respond(request, secure));
+                tokio::spawn(
+                    Http::new()
+                        .http2_only(http2)
+                        .http1_only(!http2)
+                        .serve_connection(stream, service),
+                );
+            }
+        });
+        port
+    }
+
+    // serves the same paths over tls with a self-signed certificate for localhost
+    async fn secure_server() -> u16 {
+        let identity =
+            native_tls::Identity::from_pkcs12(include_bytes!("testdata/localhost.p12"), "sonar")
+                .unwrap();
+        let acceptor =
+            async_native_tls::TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap());
+        let acceptor = Arc::new(acceptor);
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(stream) = acceptor.accept(stream).await {
+                        let service = service_fn(move |request| respond(request, port));
+                        let _ = Http::new().serve_connection(stream, service).await;
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn checks_the_status() {
+        let port = server(false, 0).await;
+        let ok = target(&format!("http://127.0.0.1:{}/ok", port), "");
+        assert_eq!(probe(&ok).await.unwrap().response_code, Some(200));
+        let teapot = target(
+            &format!("http://127.0.0.1:{}/teapot", port),
+            "expect:\n  status: [200-299]",
+        );
+        let failure = probe(&teapot).await.unwrap_err();
+        assert_eq!(failure.reason, "unexpected status code 418");
+    }
+
+    #[tokio::test]
+    async fn follows_redirects() {
+        let port = server(false, 0).await;
+        let url = format!("http://127.0.0.1:{}/first", port);
+        assert_eq!(
+            probe(&target(&url, "")).await.unwrap().response_code,
+            Some(200)
+        );
+        let unfollowed = target(&url, "http:\n  follow_redirects: false");
+        assert_eq!(probe(&unfollowed).await.unwrap().response_code, Some(301));
+    }
+
+    #[tokio::test]
+    async fn fails_on_a_redirect_loop() {
+        let port = server(false, 0).await;
+        let url = format!("http://127.0.0.1:{}/loop", port);
+        let failure = probe(&target(&url, "")).await.unwrap_err();
+        assert_eq!(failure.reason, format!("redirect loop at {}", url));
+    }
+
+    #[tokio::test]
+    async fn speaks_http2() {
+        let port = server(true, 0).await;
+        let url = format!("http://127.0.0.1:{}/ok", port);
+        let entry = probe(&target(&url, "http:\n  http2: true")).await.unwrap();
+        assert_eq!(entry.response_code, Some(200));
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_server_does_not_support_http2() {
+        let port = server(false, 0).await;
+        let url = format!("http://127.0.0.1:{}/ok", port);
+        let failure = probe(&target(&url, "http:\n  http2: true"))
+            .await
+            .unwrap_err();
+        assert_eq!(failure.reason, "server does not support h2");
+    }
+
+    #[tokio::test]
+    async fn checks_the_body() {
+        let port = server(false, 0).await;
+        let url = format!("http://127.0.0.1:{}/ok", port);
+        let matching =
+            "expect:\n  body_contains: up\n  json:\n    - pointer: /version\n      equals: 2";
+        assert!(probe(&target(&url, matching)).await.is_ok());
+        let failing = "expect:\n  json:\n    - pointer: /version\n      equals: 3";
+        let failure = probe(&target(&url, failing)).await.unwrap_err();
+        assert_eq!(failure.reason, "json pointer /version is 2 expected 3");
+    }
+
+    #[tokio::test]
+    async fn caps_the_body_the_expectations_read() {
+        let port = server(false, 0).await;
+        let url = format!("http://127.0.0.1:{}/large", port);
+        // without expectations on the body it is only drained
+        assert!(probe(&target(&url, "")).await.is_ok());
+        let failure = probe(&target(&url, "expect:\n  body_contains: b"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            failure.reason,
+            format!("response body is larger than {} bytes", MAX_BODY_SIZE)
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_the_certificate_of_https_targets() {
+        let secure = secure_server().await;
+        let url = format!("https://127.0.0.1:{}/ok", secure);
+        // the self-signed certificate is rejected, its expiry is still read
+        let failure = probe(&target(&url, "")).await.unwrap_err();
+        assert!(failure.reason.starts_with("tls handshake failed"));
+        assert!(failure.cert_expires.is_some());
+    }
+
+    #[tokio::test]
+    async fn ignores_the_certificate_of_redirects_from_http() {
+        let secure = secure_server().await;
+        let port = server(false, secure).await;
+        let url = format!("http://127.0.0.1:{}/secure", port);
+        let failure = probe(&target(&url, "")).await.unwrap_err();
+        assert!(failure.reason.starts_with("tls handshake failed"));
+        assert_eq!(failure.cert_expires, None);
+    }
+}
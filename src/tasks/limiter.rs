@@ -0,0 +1,227 @@
+use crate::config::{HostRateLimit, RateLimit};
+use prometheus::Counter;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::time::delay_for;
+
+const WINDOW: Duration = Duration::from_secs(1);
+// how often a request waiting for a free slot of its host checks again
+const CONCURRENCY_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+struct HostState {
+    running: u32,
+    sent: VecDeque<Instant>,
+}
+
+#[derive(Default)]
+struct LimiterState {
+    sent: VecDeque<Instant>,
+    hosts: HashMap<String, HostState>,
+}
+
+// a slot for a request, the host is free for another one when it is dropped
+pub struct Permit {
+    limiter: Arc<RateLimiter>,
+    host: Option<String>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(host) = &self.host {
+            let mut state = self.limiter.lock();
+            if let Some(host) = state.hosts.get_mut(host) {
+                host.running = host.running.saturating_sub(1);
+            }
+        }
+    }
+}
+
+// limits the requests of all requesters, in total and for each host
+pub struct RateLimiter {
+    max_requests_per_second: Option<u32>,
+    per_host: HostRateLimit,
+    state: Mutex<LimiterState>,
+    delayed: Counter,
+    skipped: Counter,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimit) -> Self {
+        Self {
+            max_requests_per_second: config.max_requests_per_second,
+            per_host: config.per_host.unwrap_or_default(),
+            state: Mutex::new(LimiterState::default()),
+            delayed: Counter::new("rate_limit_delayed", "requests delayed by the rate limit")
+                .expect("failed to create rate limit counter"),
+            skipped: Counter::new("rate_limit_skipped", "requests skipped by the rate limit")
+                .expect("failed to create rate limit counter"),
+        }
+    }
+
+    pub fn delayed_counter(&self) -> Counter {
+        self.delayed.clone()
+    }
+
+    pub fn skipped_counter(&self) -> Counter {
+        self.skipped.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LimiterState> {
+        self.state.lock().expect("failed to lock rate limiter")
+    }
+
+    // waits until the request is allowed. Returns the time waited, or an error and counts a skip when that would take longer than max_delay
+    pub async fn acquire(
+        self: &Arc<Self>,
+        host: Option<String>,
+        max_delay: Duration,
+    ) -> Result<(Permit, Duration), Duration> {
+        let started = Instant::now();
+        let mut delayed = false;
+        loop {
+            let wait = match self.try_acquire(host.as_deref()) {
+                Ok(()) => {
+                    let waited = if delayed {
+                        self.delayed.inc();
+                        started.elapsed()
+                    } else {
+                        Duration::from_millis(0)
+                    };
+                    let permit = Permit {
+                        limiter: self.clone(),
+                        host,
+                    };
+                    return Ok((permit, waited));
+                }
+                Err(wait) => wait,
+            };
+            if started.elapsed() + wait > max_delay {
+                self.skipped.inc();
+                return Err(started.elapsed());
+            }
+            delayed = true;
+            delay_for(wait).await;
+        }
+    }
+
+    // takes a slot if the limits allow it, otherwise returns how long to wait before trying again
+    fn try_acquire(&self, host: Option<&str>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.lock();
+        let mut wait = Self::window_wait(&mut state.sent, self.max_requests_per_second, now);
+        if let Some(host) = host {
+            let host_state = state.hosts.entry(host.to_string()).or_default();
+            let host_wait = Self::window_wait(
+                &mut host_state.sent,
+                self.per_host.max_requests_per_second,
+                now,
+            );
+            wait = wait.max(host_wait);
+            if let Some(max_concurrent) = self.per_host.max_concurrent {
+                if host_state.running >= max_concurrent {
+                    wait = wait.max(CONCURRENCY_POLL);
+                }
+            }
+            if wait > Duration::from_millis(0) {
+                return Err(wait);
+            }
+            host_state.running += 1;
+            host_state.sent.push_back(now);
+        } else if wait > Duration::from_millis(0) {
+            return Err(wait);
+        }
+        state.sent.push_back(now);
+        Ok(())
+    }
+
+    // drops requests older than a second and returns how long until another one fits
+    fn window_wait(sent: &mut VecDeque<Instant>, limit: Option<u32>, now: Instant) -> Duration {
+        while sent
+            .front()
+            .is_some_and(|s| now.duration_since(*s) >= WINDOW)
+        {
+            sent.pop_front();
+        }
+        match (limit, sent.front()) {
+            (Some(limit), Some(oldest)) if sent.len() as u32 >= limit => {
+                WINDOW - now.duration_since(*oldest)
+            }
+            _ => Duration::from_millis(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(config: &str) -> Arc<RateLimiter> {
+        Arc::new(RateLimiter::new(serde_yaml::from_str(config).unwrap()))
+    }
+
+    fn host(name: &str) -> Option<String> {
+        Some(String::from(name))
+    }
+
+    #[test]
+    fn limits_the_requests_per_second() {
+        let limiter = limiter("max_requests_per_second: 2");
+        assert!(limiter.try_acquire(None).is_ok());
+        assert!(limiter.try_acquire(Some("a")).is_ok());
+        let wait = limiter.try_acquire(Some("b")).unwrap_err();
+        assert!(wait > Duration::from_millis(900) && wait <= WINDOW);
+    }
+
+    #[test]
+    fn limits_the_requests_per_second_of_each_host() {
+        let limiter = limiter("per_host:\n  max_requests_per_second: 1");
+        assert!(limiter.try_acquire(Some("a")).is_ok());
+        assert!(limiter.try_acquire(Some("a")).is_err());
+        assert!(limiter.try_acquire(Some("b")).is_ok());
+        assert!(limiter.try_acquire(None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn limits_the_concurrent_requests_of_each_host() {
+        let limiter = limiter("per_host:\n  max_concurrent: 1");
+        let wait = Duration::from_secs(1);
+        let (permit, _) = limiter.acquire(host("a"), wait).await.ok().unwrap();
+        assert_eq!(limiter.try_acquire(Some("a")), Err(CONCURRENCY_POLL));
+        assert!(limiter.acquire(host("b"), wait).await.is_ok());
+        drop(permit);
+        assert!(limiter.try_acquire(Some("a")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_free_slot() {
+        let limiter = limiter("per_host:\n  max_concurrent: 1");
+        let (permit, _) = limiter
+            .acquire(host("a"), Duration::from_secs(1))
+            .await
+            .ok()
+            .unwrap();
+        tokio::spawn(async move {
+            delay_for(Duration::from_millis(100)).await;
+            drop(permit);
+        });
+        let (_, waited) = limiter
+            .acquire(host("a"), Duration::from_secs(1))
+            .await
+            .ok()
+            .unwrap();
+        assert!(waited >= Duration::from_millis(100));
+        assert_eq!(limiter.delayed_counter().get() as u64, 1);
+    }
+
+    #[tokio::test]
+    async fn skips_requests_that_would_wait_too_long() {
+        let limiter = limiter("max_requests_per_second: 1");
+        let wait = Duration::from_millis(100);
+        assert!(limiter.acquire(None, wait).await.is_ok());
+        assert!(limiter.acquire(None, wait).await.is_err());
+        assert_eq!(limiter.skipped_counter().get() as u64, 1);
+        assert_eq!(limiter.delayed_counter().get() as u64, 0);
+    }
+}
@@ -1,2 +1,17 @@
+pub mod alertmanager;
+pub mod dns;
+pub mod email;
+pub mod exec;
 pub mod file;
+pub mod grpc;
+pub mod heartbeat;
+pub mod hook;
 pub mod http;
+pub mod limiter;
+pub mod notifier;
+pub mod probe;
+pub mod requester;
+pub mod state;
+pub mod tcp;
+pub mod webhook;
+pub mod websocket;
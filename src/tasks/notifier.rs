@@ -0,0 +1,465 @@
+use crate::{
+    config::{
+        notification::{NotificationPolicy, NotifyOn},
+        Config, Retry, Target,
+    },
+    messages::{EntryDTO, FailureDTO, Notification, StateChange, TargetState},
+    tasks::{
+        alertmanager::AlertmanagerNotifier, email::EmailNotifier, exec::ExecNotifier,
+        webhook::WebhookNotifier,
+    },
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::*;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, RecvError};
+use tokio::time::{delay_for, delay_until};
+
+// A single destination of notifications. Deciding what to notify about is handled by the notifier task
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    fn notifies_on(&self, on: NotifyOn) -> bool;
+    // escalation notifiers only hear about escalated incidents
+    fn is_escalation(&self) -> bool;
+    // where the notification goes, used in log lines
+    fn describe(&self) -> String;
+    // notifiers that forget an incident unless they hear about it regularly, like alertmanager
+    fn refresh_interval(&self) -> Option<Duration> {
+        None
+    }
+    async fn notify(&self, notification: &Notification) -> Result<(), String>;
+}
+
+// sends a notification with the attempt timeout of the retries and redelivers it as configured by them
+pub async fn deliver<F, Fut>(
+    describe: &str,
+    retry: Option<&Retry>,
+    timeout: Duration,
+    send: F,
+) -> Result<(), String>
+where
+    F: Fn(Duration) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let attempt_timeout = retry
+        .and_then(|r| r.attempt_timeout)
+        .map(|t| t.into())
+        .unwrap_or(timeout);
+
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match (send(attempt_timeout).await, retry) {
+            (Ok(()), _) => return Ok(()),
+            (Err(err), Some(retry)) if attempts <= retry.count => {
+                let delay = retry.delay_before(attempts);
+                debug!(
+                    "{} failed: {} - retrying in {}ms",
+                    describe,
+                    err,
+                    delay.as_millis()
+                );
+                delay_for(delay).await;
+            }
+            (Err(err), _) => return Err(format!("{} after {} attempts", err, attempts)),
+        }
+    }
+}
+
+// creates the notifiers of a target from its own and the global ones its routes pick
+pub fn for_target(target: &Target, config: &Config) -> Vec<Arc<dyn Notifier>> {
+    let routed = config.route(target);
+    if let Some(receivers) = &routed {
+        debug!("{} - routed to {:?}", target.describe(), receivers);
+    }
+    // all global notifiers are notified when no route matches
+    let picked = |name: &Option<String>| match &routed {
+        Some(receivers) => name.as_ref().is_some_and(|n| receivers.contains(n)),
+        None => true,
+    };
+
+    let mut notifiers: Vec<Arc<dyn Notifier>> = Vec::new();
+    let webhooks = config
+        .global_webhooks()
+        .into_iter()
+        .filter(|w| picked(&w.name));
+    for webhook in webhooks.chain(target.webhooks.clone().into_iter().flatten()) {
+        notifiers.push(Arc::new(WebhookNotifier::new(webhook)));
+    }
+    let emails = config
+        .global_emails()
+        .into_iter()
+        .filter(|e| picked(&e.name));
+    for email in emails.chain(target.emails.clone().into_iter().flatten()) {
+        notifiers.push(Arc::new(EmailNotifier::new(email)));
+    }
+    let alertmanagers = config
+        .global_alertmanagers()
+        .into_iter()
+        .filter(|a| picked(&a.name));
+    for alertmanager in alertmanagers.chain(target.alertmanagers.clone().into_iter().flatten()) {
+        notifiers.push(Arc::new(AlertmanagerNotifier::new(alertmanager)));
+    }
+    for exec in config
+        .global_execs()
+        .into_iter()
+        .filter(|e| picked(&e.name))
+    {
+        notifiers.push(Arc::new(ExecNotifier::new(exec)));
+    }
+    notifiers
+}
+
+// a target that went down and has not recovered yet
+#[derive(Debug, Clone)]
+pub struct Incident {
+    pub down_since: DateTime<Utc>,
+    pub reason: Option<String>,
+    opened: Instant,
+    last_notified: Instant,
+    last_refreshed: Instant,
+    escalated: bool,
+}
+
+// open incidents by target name. Kept by the command so a config reload doesn't notify about the same incident again
+pub type Incidents = Arc<Mutex<HashMap<String, Incident>>>;
+
+enum Event {
+    Result(Result<EntryDTO, FailureDTO>),
+    StateChange(StateChange),
+    // it is time to renotify or escalate
+    Deadline,
+    Lagged,
+    Stop,
+}
+
+// turns the results and state changes of a target into notifications
+pub struct NotifierTask {
+    notifiers: Vec<Arc<dyn Notifier>>,
+    policy: NotificationPolicy,
+    incidents: Incidents,
+    results: broadcast::Receiver<Result<EntryDTO, FailureDTO>>,
+    state_changes: broadcast::Receiver<StateChange>,
+    // renotifications and escalations wait while the target is flapping
+    flapping: bool,
+}
+
+impl NotifierTask {
+    pub fn new(
+        notifiers: Vec<Arc<dyn Notifier>>,
+        policy: NotificationPolicy,
+        incidents: Incidents,
+        results: broadcast::Receiver<Result<EntryDTO, FailureDTO>>,
+        state_changes: broadcast::Receiver<StateChange>,
+    ) -> Self {
+        Self {
+            notifiers,
+            policy,
+            incidents,
+            results,
+            state_changes,
+            flapping: false,
+        }
+    }
+
+    pub async fn run(mut self, target: Target) {
+        let name = target.clone_unwrap_name();
+        loop {
+            match self.next_event(&target).await {
+                // maintenance results are expected to fail and not worth a notification
+                Event::Result(Ok(entry)) if entry.maintenance => (),
+                Event::Result(Err(failure)) if failure.maintenance => (),
+                Event::Result(result) => {
+                    let notification = Notification::from_result(&result);
+                    self.send_to(&notification, |n| {
+                        !n.is_escalation() && n.notifies_on(NotifyOn::Result)
+                    });
+                }
+                Event::StateChange(change) if change.target.name.as_ref() == Some(&name) => {
+                    self.flapping = change.to == TargetState::Flapping;
+                    self.handle_state_change(change)
+                }
+                Event::StateChange(_) => (),
+                Event::Deadline => self.handle_deadline(&target),
+                Event::Lagged => (),
+                Event::Stop => break,
+            }
+        }
+        debug!("stopped notifier for {}", target.describe());
+    }
+
+    async fn next_event(&mut self, target: &Target) -> Event {
+        let deadline = self.next_deadline(&target.clone_unwrap_name());
+        let deadline = async move {
+            match deadline {
+                Some(deadline) => delay_until(deadline.into()).await,
+                None => futures::future::pending::<()>().await,
+            }
+        };
+        tokio::select! {
+            result = self.results.recv() => match result {
+                Ok(result) => Event::Result(result),
+                Err(RecvError::Closed) => Event::Stop,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("{} - notifier is lagging behind with: {}", target.describe(), n);
+                    Event::Lagged
+                }
+            },
+            change = self.state_changes.recv() => match change {
+                Ok(change) => Event::StateChange(change),
+                Err(RecvError::Closed) => Event::Stop,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("{} - notifier is lagging behind with: {}", target.describe(), n);
+                    Event::Lagged
+                }
+            },
+            _ = deadline => Event::Deadline,
+        }
+    }
+
+    // a target that goes down opens an incident and one that comes up resolves it
+    fn handle_state_change(&self, change: StateChange) {
+        let name = change.target.clone_unwrap_name();
+        let mut incidents = self.incidents.lock().expect("failed to lock incidents");
+        match change.to {
+            TargetState::Down => {
+                if let Some(incident) = incidents.get(&name) {
+                    debug!(
+                        "{} - incident since {} is already notified",
+                        change.target.describe(),
+                        incident.down_since.to_rfc3339()
+                    );
+                    return;
+                }
+                let now = Instant::now();
+                incidents.insert(
+                    name,
+                    Incident {
+                        down_since: change.time,
+                        reason: change.reason.clone(),
+                        opened: now,
+                        last_notified: now,
+                        last_refreshed: now,
+                        escalated: false,
+                    },
+                );
+                drop(incidents);
+                let mut notification = Notification::from_state_change(NotifyOn::Failure, &change);
+                notification.down_since = Some(change.time);
+                self.send_to(&notification, |n| {
+                    !n.is_escalation() && n.notifies_on(NotifyOn::Failure)
+                });
+            }
+            TargetState::Up => {
+                let incident = match incidents.remove(&name) {
+                    Some(incident) => incident,
+                    None => return,
+                };
+                drop(incidents);
+                let mut notification = Notification::from_state_change(NotifyOn::Recovery, &change);
+                notification.down_since = Some(incident.down_since);
+                notification.escalated = incident.escalated;
+                // everyone told about the incident hears that it is resolved
+                self.send_to(&notification, |n| {
+                    if n.is_escalation() {
+                        incident.escalated
+                    } else {
+                        n.notifies_on(NotifyOn::Failure) || n.notifies_on(NotifyOn::Recovery)
+                    }
+                });
+            }
+            // transitions are hidden while flapping, the incident stays as it was
+            TargetState::Degraded | TargetState::Unknown | TargetState::Flapping => (),
+        }
+    }
+
+    // all notifiers that need it are refreshed at the shortest interval
+    fn refresh_interval(&self) -> Option<Duration> {
+        self.notifiers
+            .iter()
+            .filter_map(|n| n.refresh_interval())
+            .min()
+    }
+
+    // when the open incident of the target is due for a renotification, escalation or refresh
+    fn next_deadline(&self, name: &str) -> Option<Instant> {
+        let incidents = self.incidents.lock().expect("failed to lock incidents");
+        let incident = incidents.get(name)?;
+        let refresh = self
+            .refresh_interval()
+            .map(|every| incident.last_refreshed + every);
+        // only the refresh keeps going while flapping, it is not a new notification
+        if self.flapping {
+            return refresh;
+        }
+        let renotify = self
+            .policy
+            .renotify_interval()
+            .map(|after| incident.last_notified + after);
+        let escalate = match incident.escalated {
+            true => None,
+            false => self
+                .policy
+                .escalation_delay()
+                .map(|after| incident.opened + after),
+        };
+        renotify.into_iter().chain(escalate).chain(refresh).min()
+    }
+
+    fn handle_deadline(&self, target: &Target) {
+        let now = Instant::now();
+        let mut incidents = self.incidents.lock().expect("failed to lock incidents");
+        let incident = match incidents.get_mut(&target.clone_unwrap_name()) {
+            Some(incident) => incident,
+            None => return,
+        };
+        let escalate = !self.flapping
+            && !incident.escalated
+            && self
+                .policy
+                .escalation_delay()
+                .is_some_and(|after| now >= incident.opened + after);
+        let renotify = !self.flapping
+            && self
+                .policy
+                .renotify_interval()
+                .is_some_and(|after| now >= incident.last_notified + after);
+        let refresh = self
+            .refresh_interval()
+            .is_some_and(|every| now >= incident.last_refreshed + every);
+        if escalate {
+            incident.escalated = true;
+        }
+        if renotify {
+            incident.last_notified = now;
+        }
+        // a renotification refreshes as well
+        if renotify || refresh {
+            incident.last_refreshed = now;
+        }
+        let incident = incident.clone();
+        drop(incidents);
+
+        let notification = Notification {
+            on: NotifyOn::Failure,
+            time: Utc::now(),
+            state: Some(TargetState::Down),
+            previous_state: None,
+            reason: incident.reason.clone(),
+            latency: None,
+            response_code: None,
+            down_since: Some(incident.down_since),
+            escalated: incident.escalated,
+            suppressed: false,
+            target: target.clone(),
+        };
+        if escalate {
+            info!(
+                "{} - escalating incident since {}",
+                target.describe(),
+                incident.down_since.to_rfc3339()
+            );
+            self.send_to(&notification, |n| n.is_escalation());
+        }
+        if renotify {
+            // escalation notifiers that were just notified are left out
+            self.send_to(&notification, |n| {
+                if n.is_escalation() {
+                    incident.escalated && !escalate
+                } else {
+                    n.notifies_on(NotifyOn::Failure)
+                }
+            });
+        } else if refresh {
+            self.send_to(&notification, |n| n.refresh_interval().is_some());
+        }
+    }
+
+    // sends the notification in the background to the notifiers that pass the filter, so a slow notifier doesn't hold back the others
+    fn send_to<F: Fn(&dyn Notifier) -> bool>(&self, notification: &Notification, filter: F) {
+        for notifier in self.notifiers.iter() {
+            if filter(notifier.as_ref()) {
+                tokio::spawn(Self::send(notifier.clone(), notification.clone()));
+            }
+        }
+    }
+
+    async fn send(notifier: Arc<dyn Notifier>, notification: Notification) {
+        match notifier.notify(&notification).await {
+            Ok(()) => debug!(
+                "{} - sent {} notification to {}",
+                notification.target.describe(),
+                notification.on,
+                notifier.describe()
+            ),
+            Err(err) => warn!(
+                "{} - failed to send {} notification to {}: {}",
+                notification.target.describe(),
+                notification.on,
+                notifier.describe(),
+                err
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(routes: &str) -> Config {
+        let yaml = format!(
+            "targets:
+  - name: api
+    url: http://localhost/api
+    tags: [production]
+    webhooks:
+      - url: http://localhost/own
+webhooks:
+  - name: chat
+    url: http://localhost/chat
+  - url: http://localhost/unnamed
+execs:
+  - name: log
+    command: logger
+{}",
+            routes
+        );
+        serde_yaml::from_str::<Config>(&yaml).unwrap().hydrate()
+    }
+
+    fn notified(config: &Config) -> Vec<String> {
+        let mut notified: Vec<String> = for_target(&config.targets[0], config)
+            .iter()
+            .map(|n| n.describe())
+            .collect();
+        notified.sort();
+        notified
+    }
+
+    #[test]
+    fn notifies_all_global_notifiers_when_no_route_matches() {
+        let all = vec![
+            "exec logger",
+            "webhook http://localhost/chat",
+            "webhook http://localhost/own",
+            "webhook http://localhost/unnamed",
+        ];
+        assert_eq!(notified(&config("")), all);
+        let unmatched = "routes:\n  - tags: [staging]\n    receivers: [chat]";
+        assert_eq!(notified(&config(unmatched)), all);
+    }
+
+    #[test]
+    fn notifies_the_routed_and_own_notifiers() {
+        let routed = "routes:\n  - tags: [production]\n    receivers: [log]";
+        assert_eq!(
+            notified(&config(routed)),
+            vec!["exec logger", "webhook http://localhost/own"]
+        );
+    }
+}
@@ -0,0 +1,28 @@
+use crate::{
+    config::{ProbeType, Target},
+    messages::{Entry, Failure},
+    tasks::{
+        dns::DnsProbe, grpc::GrpcProbe, http::HttpProbe, tcp::TcpProbe, websocket::WebsocketProbe,
+    },
+};
+use async_trait::async_trait;
+
+// A single check of a target. Scheduling, retries and reporting are handled by the requester
+#[async_trait]
+pub trait Probe: Send + Sync {
+    async fn execute(&self, target: &Target) -> Result<Entry, Failure>;
+}
+
+// creates the probe selected by the type of the target
+pub async fn from_target(target: &Target) -> Result<Box<dyn Probe>, String> {
+    match target.clone_unwrap_type() {
+        ProbeType::Http => Ok(Box::new(HttpProbe::new(target).await?)),
+        ProbeType::Tcp => Ok(Box::new(TcpProbe::new(target)?)),
+        ProbeType::Dns => Ok(Box::new(DnsProbe::new(target).await?)),
+        ProbeType::Grpc => Ok(Box::new(GrpcProbe::new(target)?)),
+        ProbeType::Websocket => Ok(Box::new(WebsocketProbe::new(target)?)),
+        ProbeType::Heartbeat => Err(String::from(
+            "heartbeat targets are pinged by the job instead of probed",
+        )),
+    }
+}
@@ -0,0 +1,478 @@
+use crate::{
+    config::{
+        maintenance::{self, MaintenanceAction, MaintenanceWindow},
+        ScheduleStrategy, Target,
+    },
+    messages::{Entry, EntryDTO, Failure, FailureDTO},
+    tasks::{
+        limiter::{Permit, RateLimiter},
+        probe::Probe,
+        state::Dependencies,
+    },
+    utils::{hash::stable_hash, schedule},
+};
+use atomic::AtomicU32;
+use chrono::Utc;
+use chrono_tz::Tz;
+use cron::Schedule;
+use log::*;
+use rand::Rng;
+use std::sync::atomic::{self, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+use tokio::time::{delay_for, interval_at, timeout, Interval};
+
+// tracks if a target with interval_on_failure is failing and notifies the requester when it changes
+struct AdaptiveInterval {
+    state: Mutex<(bool, u32)>,
+    recover_after: u32,
+    changed: watch::Sender<bool>,
+}
+
+impl AdaptiveInterval {
+    fn new(recover_after: u32) -> (Self, watch::Receiver<bool>) {
+        let (changed, failing) = watch::channel(false);
+        (
+            Self {
+                state: Mutex::new((false, 0)),
+                recover_after,
+                changed,
+            },
+            failing,
+        )
+    }
+
+    // records the result of a request and returns if the target is failing
+    fn record(&self, success: bool) -> bool {
+        let mut state = self.state.lock().expect("failed to lock interval state");
+        let (was_failing, successes) = *state;
+        *state = match (success, was_failing) {
+            (false, _) => (true, 0),
+            (true, true) if successes + 1 >= self.recover_after => (false, 0),
+            (true, failing) => (failing, successes + 1),
+        };
+        if state.0 != was_failing {
+            let _ = self.changed.broadcast(state.0);
+        }
+        state.0
+    }
+
+    fn is_failing(&self) -> bool {
+        self.state.lock().expect("failed to lock interval state").0
+    }
+}
+
+// decides when the next request happens, either every interval or at the fire times of a cron schedule
+enum Ticker {
+    Interval(Interval),
+    Cron(Box<Schedule>, Tz),
+}
+
+impl Ticker {
+    fn cron(expression: &str, timezone: Tz) -> Result<Self, String> {
+        let schedule = schedule::parse_cron(expression)?;
+        Ok(Ticker::Cron(Box::new(schedule), timezone))
+    }
+
+    async fn tick(&mut self) {
+        match self {
+            Ticker::Interval(interval) => {
+                interval.tick().await;
+            }
+            Ticker::Cron(schedule, timezone) => match schedule.upcoming(*timezone).next() {
+                Some(next) => {
+                    let wait = (next.with_timezone(&Utc) - Utc::now())
+                        .to_std()
+                        .unwrap_or_default();
+                    delay_for(wait).await;
+                }
+                // the schedule never fires again
+                None => futures::future::pending::<()>().await,
+            },
+        }
+    }
+}
+
+pub struct IntervalRequesterTask {
+    probe: Arc<dyn Probe>,
+    broadcaster: broadcast::Sender<Result<EntryDTO, FailureDTO>>,
+    strategy: ScheduleStrategy,
+    maintenance: Vec<MaintenanceWindow>,
+    limiter: Option<Arc<RateLimiter>>,
+    dependencies: Dependencies,
+}
+
+impl IntervalRequesterTask {
+    pub fn new(
+        probe: Box<dyn Probe>,
+        broadcaster: broadcast::Sender<Result<EntryDTO, FailureDTO>>,
+        strategy: ScheduleStrategy,
+        maintenance: Vec<MaintenanceWindow>,
+        limiter: Option<Arc<RateLimiter>>,
+        dependencies: Dependencies,
+    ) -> Self {
+        Self {
+            probe: Arc::from(probe),
+            broadcaster,
+            strategy,
+            maintenance,
+            limiter,
+            dependencies,
+        }
+    }
+
+    pub async fn run(self, target: Target) {
+        debug!("Starting requester for {}", target.describe());
+        let period: Duration = target.clone_unwrap_interval().into();
+        let mut ticker = match self.ticker(&target, false, true) {
+            Ok(ticker) => ticker,
+            Err(err) => {
+                error!("{} - {}", target.describe(), err);
+                return;
+            }
+        };
+        let (adaptive, mut failing) = match target.interval_on_failure {
+            Some(_) => {
+                let (adaptive, mut failing) =
+                    AdaptiveInterval::new(target.unwrap_interval_recover_after());
+                // the first recv returns the initial value
+                failing.recv().await;
+                (Some(Arc::new(adaptive)), Some(failing))
+            }
+            None => (None, None),
+        };
+        self.next_tick(&mut ticker, &mut failing, &target).await;
+        let currently_running = Arc::from(AtomicU32::new(0));
+
+        loop {
+            let in_maintenance = match maintenance::active_action(
+                &self.maintenance,
+                &target.clone_unwrap_name(),
+                Utc::now(),
+            ) {
+                Some(MaintenanceAction::Pause) => {
+                    debug!("{} - paused for maintenance", target.describe());
+                    self.next_tick(&mut ticker, &mut failing, &target).await;
+                    continue;
+                }
+                Some(MaintenanceAction::Tag) => true,
+                None => false,
+            };
+            if currently_running.load(Ordering::SeqCst) >= target.unwrap_max_concurrent() {
+                warn!("{} - Responses are not delivered in time for more concurrent requests. Skipping a request", target.describe());
+                self.next_tick(&mut ticker, &mut failing, &target).await;
+                continue;
+            }
+            currently_running.fetch_add(1, Ordering::SeqCst);
+
+            // jitter is a share of the normal interval, so it is left out while failing
+            let is_failing = failing.as_ref().is_some_and(|f| *f.borrow());
+            let jitter = match ticker {
+                Ticker::Interval(_) if !is_failing => self.jitter(period),
+                _ => Duration::from_millis(0),
+            };
+            self.spawn_request(
+                target.clone(),
+                in_maintenance,
+                jitter,
+                currently_running.clone(),
+                adaptive.clone(),
+            );
+
+            self.next_tick(&mut ticker, &mut failing, &target).await;
+        }
+    }
+
+    // runs a single request in the background and broadcasts its result
+    fn spawn_request(
+        &self,
+        target: Target,
+        in_maintenance: bool,
+        jitter: Duration,
+        currently_running: Arc<AtomicU32>,
+        adaptive: Option<Arc<AdaptiveInterval>>,
+    ) {
+        let probe = self.probe.clone();
+        let sender = self.broadcaster.clone();
+        let limiter = self.limiter.clone();
+        let dependencies = self.dependencies.clone();
+
+        let task = async move {
+            if jitter > Duration::from_millis(0) {
+                delay_for(jitter).await;
+            }
+            let result = match Self::execute(
+                probe.as_ref(),
+                &target,
+                limiter.as_ref(),
+                adaptive.as_deref(),
+            )
+            .await
+            {
+                Some(result) => result,
+                None => {
+                    currently_running.fetch_sub(1, Ordering::SeqCst);
+                    return;
+                }
+            };
+            let failing = adaptive.map(|a| a.record(result.is_ok())).unwrap_or(false);
+            let interval_ms = Self::effective_interval(&target, failing).map(|i| i.as_millis());
+            match result {
+                Ok(mut entry) => {
+                    entry.maintenance = in_maintenance;
+                    entry.interval_ms = interval_ms;
+                    info!(
+                        "{}\t{}ms\t{}",
+                        entry.response_code_or_dash(),
+                        entry.latency,
+                        target.describe()
+                    );
+                    let _ = sender.send(Ok(entry.to_dto()));
+                }
+                Err(mut failure) => {
+                    failure.maintenance = in_maintenance;
+                    failure.interval_ms = interval_ms;
+                    // checked after the request, the parent may have gone down in the meantime
+                    let down_parent = dependencies.down_parent();
+                    failure.suppressed = down_parent.is_some();
+                    info!(
+                        "Request failure\t{}ms\t{}\t{}{}",
+                        failure.latency,
+                        target.describe(),
+                        failure.reason,
+                        down_parent
+                            .map(|p| format!("\tsuppressed, {} is down", p))
+                            .unwrap_or_default()
+                    );
+                    let _ = sender.send(Err(failure.to_dto()));
+                }
+            }
+            currently_running.fetch_sub(1, Ordering::SeqCst);
+        };
+        tokio::spawn(task);
+    }
+
+    // waits for the rate limit. A request that cannot be sent within the interval in effect is skipped so requests don't pile up
+    async fn limit(
+        limiter: &Arc<RateLimiter>,
+        target: &Target,
+        adaptive: Option<&AdaptiveInterval>,
+    ) -> Option<Permit> {
+        let failing = adaptive.map(|a| a.is_failing()).unwrap_or(false);
+        let max_delay = Self::effective_interval(target, failing)
+            .unwrap_or_else(|| target.clone_unwrap_timeout().into());
+        match limiter.acquire(target.host(), max_delay).await {
+            Ok((permit, waited)) => {
+                if waited > Duration::from_millis(0) {
+                    info!(
+                        "{} - delayed {}ms by the rate limit",
+                        target.describe(),
+                        waited.as_millis()
+                    );
+                }
+                Some(permit)
+            }
+            Err(waited) => {
+                warn!(
+                    "{} - Rate limit exceeded for {}ms. Skipping a request",
+                    target.describe(),
+                    waited.as_millis()
+                );
+                None
+            }
+        }
+    }
+
+    // the ticker of the target, interval_on_failure while it is failing
+    fn ticker(&self, target: &Target, failing: bool, first: bool) -> Result<Ticker, String> {
+        let now = tokio::time::Instant::now();
+        if let (true, Some(interval_on_failure)) = (failing, target.interval_on_failure) {
+            let period: Duration = interval_on_failure.into();
+            return Ok(Ticker::Interval(interval_at(now + period, period)));
+        }
+        if let Some(expression) = &target.schedule {
+            return Ticker::cron(expression, target.timezone.unwrap_or(Tz::UTC))
+                .map_err(|err| format!("invalid schedule '{}': {}", expression, err));
+        }
+        let period: Duration = target.clone_unwrap_interval().into();
+        let delay = if first {
+            let delay = self.first_tick_delay(target, period);
+            debug!(
+                "{} - first request in {}ms",
+                target.describe(),
+                delay.as_millis()
+            );
+            delay
+        } else {
+            period
+        };
+        Ok(Ticker::Interval(interval_at(now + delay, period)))
+    }
+
+    // waits for the next tick. When the target starts or stops failing the ticker is switched and the wait starts over
+    async fn next_tick(
+        &self,
+        ticker: &mut Ticker,
+        failing: &mut Option<watch::Receiver<bool>>,
+        target: &Target,
+    ) {
+        loop {
+            let changed = match failing {
+                Some(failing) => tokio::select! {
+                    _ = ticker.tick() => return,
+                    changed = failing.recv() => changed,
+                },
+                None => {
+                    ticker.tick().await;
+                    return;
+                }
+            };
+            let failing = match changed {
+                Some(failing) => failing,
+                None => {
+                    ticker.tick().await;
+                    return;
+                }
+            };
+            match self.ticker(target, failing, false) {
+                Ok(next) => {
+                    debug!(
+                        "{} - {} interval on failure",
+                        target.describe(),
+                        if failing {
+                            "switching to"
+                        } else {
+                            "reverting from"
+                        }
+                    );
+                    *ticker = next;
+                }
+                Err(err) => error!("{} - {}", target.describe(), err),
+            }
+        }
+    }
+
+    fn effective_interval(target: &Target, failing: bool) -> Option<Duration> {
+        match (failing, target.interval_on_failure, &target.schedule) {
+            (true, Some(interval_on_failure), _) => Some(interval_on_failure.into()),
+            (_, _, Some(_)) => None,
+            _ => Some(target.clone_unwrap_interval().into()),
+        }
+    }
+
+    // the spread strategies give each target a fixed phase in the wall clock, so restarts keep it
+    fn first_tick_delay(&self, target: &Target, period: Duration) -> Duration {
+        let period_ms = period.as_millis() as u64;
+        if self.strategy == ScheduleStrategy::Burst || period_ms == 0 {
+            return Duration::from_millis(0);
+        }
+        let phase = stable_hash(&target.clone_unwrap_name()) % period_ms;
+        let now = Utc::now().timestamp_millis() as u64 % period_ms;
+        Duration::from_millis((phase + period_ms - now) % period_ms)
+    }
+
+    fn jitter(&self, period: Duration) -> Duration {
+        let max_ms = period.as_millis() as u64 / 10;
+        if self.strategy != ScheduleStrategy::RandomJitter || max_ms == 0 {
+            return Duration::from_millis(0);
+        }
+        Duration::from_millis(rand::thread_rng().gen_range(0, max_ms))
+    }
+
+    // executes the probe, retrying failed attempts as configured by the target. Each attempt waits for the
+    // rate limit and frees its slot before the retry delay. None when the first attempt is skipped
+    async fn execute(
+        probe: &dyn Probe,
+        target: &Target,
+        limiter: Option<&Arc<RateLimiter>>,
+        adaptive: Option<&AdaptiveInterval>,
+    ) -> Option<Result<Entry, Failure>> {
+        let retry = target.retries.clone();
+        let attempt_timeout: Duration = retry
+            .as_ref()
+            .and_then(|r| r.attempt_timeout)
+            .unwrap_or_else(|| target.clone_unwrap_timeout())
+            .into();
+
+        let mut attempts = 0;
+        let mut last_failure: Option<Failure> = None;
+        loop {
+            let permit = match limiter {
+                Some(limiter) => match Self::limit(limiter, target, adaptive).await {
+                    Some(permit) => Some(permit),
+                    // a skipped retry reports the failure of the previous attempt
+                    None => return last_failure.map(Err),
+                },
+                None => None,
+            };
+            attempts += 1;
+            debug!("Sending {} attempt {}", target.describe(), attempts);
+            let started = Instant::now();
+            let result = match timeout(attempt_timeout, probe.execute(target)).await {
+                Ok(result) => result,
+                Err(_) => Err(Failure::new(
+                    Utc::now(),
+                    started.elapsed().as_millis(),
+                    format!("timed out after {}ms", attempt_timeout.as_millis()),
+                    None,
+                    attempts,
+                    target.clone(),
+                )),
+            };
+            match (result, &retry) {
+                (Err(mut failure), Some(retry)) if attempts <= retry.count => {
+                    let delay = retry.delay_before(attempts);
+                    debug!(
+                        "{} attempt {} failed: {} - retrying in {}ms",
+                        target.describe(),
+                        attempts,
+                        failure.reason,
+                        delay.as_millis()
+                    );
+                    drop(permit);
+                    delay_for(delay).await;
+                    failure.attempts = attempts;
+                    last_failure = Some(failure);
+                }
+                (Ok(mut entry), _) => {
+                    entry.attempts = attempts;
+                    return Some(Ok(entry));
+                }
+                (Err(mut failure), _) => {
+                    failure.attempts = attempts;
+                    return Some(Err(failure));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    #[tokio::test]
+    async fn ticks_at_the_fire_times_of_a_cron_schedule() {
+        // every second, with the optional seconds field
+        let mut ticker = Ticker::cron("* * * * * *", Tz::Europe__Copenhagen).unwrap();
+        ticker.tick().await;
+        let first = Utc::now();
+        ticker.tick().await;
+        let second = Utc::now();
+        assert_ne!(first.second(), second.second());
+        assert!(second - first < chrono::Duration::milliseconds(1100));
+    }
+
+    #[test]
+    fn accepts_cron_expressions_without_seconds() {
+        assert!(Ticker::cron("*/5 9-17 * * MON-FRI", Tz::UTC).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_cron_expressions() {
+        assert!(Ticker::cron("every minute", Tz::UTC).is_err());
+        assert!(Ticker::cron("61 * * * *", Tz::UTC).is_err());
+    }
+}
@@ -0,0 +1,464 @@
+use crate::{
+    config::{FlappingOptions, Target},
+    messages::{EntryDTO, FailureDTO, StateChange, TargetState},
+};
+use chrono::{TimeZone, Utc};
+use log::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, RecvError};
+
+// tracks the rate of changes between success and failure over the latest results
+struct FlappingDetector {
+    results: VecDeque<bool>,
+    window: usize,
+    ratio: f64,
+    flapping: bool,
+}
+
+impl FlappingDetector {
+    fn new(options: &FlappingOptions) -> Self {
+        Self {
+            results: VecDeque::new(),
+            window: options.unwrap_window().max(2) as usize,
+            ratio: options.unwrap_ratio(),
+            flapping: false,
+        }
+    }
+
+    // records a result and returns if the target is flapping
+    fn record(&mut self, success: bool) -> bool {
+        self.results.push_back(success);
+        if self.results.len() > self.window {
+            self.results.pop_front();
+        }
+        // undecided until the window is full
+        if self.results.len() < self.window {
+            return self.flapping;
+        }
+        let changes = self
+            .results
+            .iter()
+            .zip(self.results.iter().skip(1))
+            .filter(|(previous, next)| previous != next)
+            .count();
+        let rate = changes as f64 / (self.results.len() - 1) as f64;
+        // a lower ratio to stop flapping keeps it from flapping in and out
+        self.flapping = match self.flapping {
+            false => rate >= self.ratio,
+            true => rate >= self.ratio / 2.0,
+        };
+        self.flapping
+    }
+}
+
+// counts consecutive results and decides the state of a target
+pub struct StateMachine {
+    // the state from the thresholds, hidden while flapping
+    health: TargetState,
+    failures: u32,
+    successes: u32,
+    failure_threshold: u32,
+    recovery_threshold: u32,
+    flapping: Option<FlappingDetector>,
+}
+
+impl StateMachine {
+    pub fn new(target: &Target) -> Self {
+        Self {
+            health: TargetState::Unknown,
+            failures: 0,
+            successes: 0,
+            failure_threshold: target.unwrap_failure_threshold(),
+            recovery_threshold: target.unwrap_recovery_threshold(),
+            flapping: target.flapping.as_ref().map(FlappingDetector::new),
+        }
+    }
+
+    // continues from the health a target had before the config was reloaded. A flapping target keeps
+    // its health, whether it flaps is decided again once the window is full
+    pub fn resume(target: &Target, previous: Option<TargetState>) -> Self {
+        let mut machine = Self::new(target);
+        match previous {
+            // a down target still has to recover
+            Some(TargetState::Down) => {
+                machine.health = TargetState::Down;
+                machine.failures = machine.failure_threshold;
+            }
+            Some(TargetState::Degraded) => {
+                machine.health = TargetState::Degraded;
+                machine.failures = 1;
+            }
+            Some(TargetState::Up) => machine.health = TargetState::Up,
+            _ => (),
+        }
+        machine
+    }
+
+    // the state from the thresholds, also while the target is flapping
+    pub fn health(&self) -> TargetState {
+        self.health
+    }
+
+    pub fn state(&self) -> TargetState {
+        match &self.flapping {
+            Some(detector) if detector.flapping => TargetState::Flapping,
+            _ => self.health,
+        }
+    }
+
+    // records a result and returns the new state if it changed
+    pub fn record(&mut self, success: bool) -> Option<TargetState> {
+        let previous = self.state();
+        if success {
+            self.failures = 0;
+            self.successes += 1;
+        } else {
+            self.successes = 0;
+            self.failures += 1;
+        }
+        self.health = match (success, self.health) {
+            // a down target stays down until it has recovered
+            (true, TargetState::Down) if self.successes < self.recovery_threshold => {
+                TargetState::Down
+            }
+            (true, _) => TargetState::Up,
+            (false, _) if self.failures >= self.failure_threshold => TargetState::Down,
+            (false, TargetState::Down) => TargetState::Down,
+            (false, _) => TargetState::Degraded,
+        };
+        if let Some(detector) = self.flapping.as_mut() {
+            detector.record(success);
+        }
+        let next = self.state();
+        if next == previous {
+            return None;
+        }
+        Some(next)
+    }
+}
+
+// the health of each target by name, shared so targets can look up the targets they depend on.
+// It is the state from the thresholds, so a flapping parent that is down still suppresses failures
+pub type TargetStates = Arc<Mutex<HashMap<String, TargetState>>>;
+
+// the targets a target depends on
+#[derive(Clone)]
+pub struct Dependencies {
+    parents: Vec<String>,
+    states: TargetStates,
+}
+
+impl Dependencies {
+    pub fn new(target: &Target, states: TargetStates) -> Self {
+        Self {
+            parents: target.depends_on.clone().unwrap_or_default(),
+            states,
+        }
+    }
+
+    // the name of a parent that is down, failures are suppressed while there is one
+    pub fn down_parent(&self) -> Option<String> {
+        if self.parents.is_empty() {
+            return None;
+        }
+        let states = self.states.lock().expect("failed to lock target states");
+        self.parents
+            .iter()
+            .find(|p| states.get(*p) == Some(&TargetState::Down))
+            .cloned()
+    }
+}
+
+// follows the results of a target and sends a state change when its state changes
+pub struct StateTrackerTask {
+    receiver: broadcast::Receiver<Result<EntryDTO, FailureDTO>>,
+    sender: broadcast::Sender<StateChange>,
+    states: TargetStates,
+}
+
+impl StateTrackerTask {
+    pub fn new(
+        receiver: broadcast::Receiver<Result<EntryDTO, FailureDTO>>,
+        sender: broadcast::Sender<StateChange>,
+        states: TargetStates,
+    ) -> Self {
+        Self {
+            receiver,
+            sender,
+            states,
+        }
+    }
+
+    fn publish(&self, target: &Target, machine: &StateMachine) {
+        self.states
+            .lock()
+            .expect("failed to lock target states")
+            .insert(target.clone_unwrap_name(), machine.health());
+    }
+
+    pub async fn run(mut self, target: Target) {
+        let previous = self
+            .states
+            .lock()
+            .expect("failed to lock target states")
+            .get(&target.clone_unwrap_name())
+            .copied();
+        let mut machine = StateMachine::resume(&target, previous);
+        self.publish(&target, &machine);
+        loop {
+            let (success, reason, latency, timestamp_seconds) = match self.receiver.recv().await {
+                // failures are expected in maintenance windows
+                Ok(Ok(entry)) if entry.maintenance => continue,
+                Ok(Err(failure)) if failure.maintenance => continue,
+                // failures while a parent is down are not the fault of the target
+                Ok(Err(failure)) if failure.suppressed => continue,
+                Ok(Ok(entry)) => (true, None, entry.latency, entry.timestamp_seconds),
+                Ok(Err(failure)) => (
+                    false,
+                    Some(failure.reason),
+                    failure.latency,
+                    failure.timestamp_seconds,
+                ),
+                Err(RecvError::Closed) => {
+                    debug!("stopped state tracker for {}", target.describe());
+                    return;
+                }
+                Err(RecvError::Lagged(n)) => {
+                    warn!(
+                        "{} - state tracker is lagging behind with: {}",
+                        target.describe(),
+                        n
+                    );
+                    continue;
+                }
+            };
+            let from = machine.state();
+            let changed = machine.record(success);
+            // the health can change while the target keeps flapping
+            self.publish(&target, &machine);
+            if let Some(to) = changed {
+                let change = StateChange {
+                    time: Utc.timestamp(timestamp_seconds, 0),
+                    from,
+                    to,
+                    reason,
+                    latency,
+                    target: target.clone(),
+                };
+                info!(
+                    "{} - {} -> {} at {}{}",
+                    target.describe(),
+                    change.from,
+                    change.to,
+                    change.time.to_rfc3339(),
+                    change
+                        .reason
+                        .as_ref()
+                        .map(|r| format!(": {}", r))
+                        .unwrap_or_default()
+                );
+                let _ = self.sender.send(change);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{Entry, Failure, Timings};
+
+    fn target() -> Target {
+        serde_yaml::from_str(
+            "name: api\nurl: http://localhost\nfailure_threshold: 2\nrecovery_threshold: 2",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn resumes_a_down_target() {
+        let mut machine = StateMachine::resume(&target(), Some(TargetState::Down));
+        assert_eq!(machine.state(), TargetState::Down);
+        assert_eq!(machine.record(false), None);
+        assert_eq!(machine.record(true), None);
+        assert_eq!(machine.record(true), Some(TargetState::Up));
+    }
+
+    #[test]
+    fn resumes_a_degraded_target() {
+        let mut machine = StateMachine::resume(&target(), Some(TargetState::Degraded));
+        assert_eq!(machine.record(false), Some(TargetState::Down));
+    }
+
+    #[test]
+    fn starts_unknown_without_a_previous_state() {
+        let mut machine = StateMachine::resume(&target(), None);
+        assert_eq!(machine.state(), TargetState::Unknown);
+        assert_eq!(machine.record(false), Some(TargetState::Degraded));
+    }
+
+    fn flapping_target(window: u32) -> Target {
+        serde_yaml::from_str(&format!(
+            "name: api\nurl: http://localhost\nfailure_threshold: 1\nrecovery_threshold: 1\nflapping:\n  window: {}\n  ratio: 0.5",
+            window
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn resumes_the_health_of_a_flapping_target() {
+        let target = flapping_target(3);
+        let mut machine = StateMachine::new(&target);
+        for success in &[false, true, false] {
+            machine.record(*success);
+        }
+        assert_eq!(machine.state(), TargetState::Flapping);
+        assert_eq!(machine.health(), TargetState::Down);
+        let mut machine = StateMachine::resume(&target, Some(machine.health()));
+        assert_eq!(machine.state(), TargetState::Down);
+        assert_eq!(machine.record(true), Some(TargetState::Up));
+    }
+
+    fn detector(window: u32, ratio: f64) -> FlappingDetector {
+        FlappingDetector::new(
+            &serde_yaml::from_str(&format!("window: {}\nratio: {}", window, ratio)).unwrap(),
+        )
+    }
+
+    #[test]
+    fn decides_flapping_once_the_window_is_full() {
+        let mut detector = detector(4, 0.5);
+        assert!(!detector.record(true));
+        assert!(!detector.record(false));
+        assert!(!detector.record(true));
+        // 3 of 3 changed
+        assert!(detector.record(false));
+    }
+
+    #[test]
+    fn flaps_at_the_ratio_of_changes() {
+        let mut detector = detector(5, 0.5);
+        // 1 of 4 changed
+        for success in &[true, true, true, false, false] {
+            assert!(!detector.record(*success));
+        }
+        // 2 of 4 changed
+        assert!(detector.record(true));
+    }
+
+    #[test]
+    fn stops_flapping_below_half_the_ratio() {
+        let mut detector = detector(5, 0.5);
+        for success in &[true, false, true, false, true] {
+            detector.record(*success);
+        }
+        assert!(detector.flapping);
+        // the rate drops to 3/4, 2/4 and 1/4, which is still at half the ratio
+        assert!(detector.record(true));
+        assert!(detector.record(true));
+        assert!(detector.record(true));
+        // no changes left in the window
+        assert!(!detector.record(true));
+    }
+
+    fn states(entries: &[(&str, TargetState)]) -> TargetStates {
+        let states = entries
+            .iter()
+            .map(|(name, state)| (name.to_string(), *state))
+            .collect();
+        Arc::new(Mutex::new(states))
+    }
+
+    fn dependent(parents: &str) -> Target {
+        serde_yaml::from_str::<Target>(&format!(
+            "name: app\nurl: http://localhost\ndepends_on: [{}]",
+            parents
+        ))
+        .unwrap()
+        .hydrate()
+    }
+
+    #[test]
+    fn finds_a_parent_that_is_down() {
+        let states = states(&[("db", TargetState::Up), ("cache", TargetState::Down)]);
+        let dependencies = Dependencies::new(&dependent("db, cache"), states.clone());
+        assert_eq!(dependencies.down_parent(), Some(String::from("cache")));
+        states
+            .lock()
+            .unwrap()
+            .insert(String::from("cache"), TargetState::Degraded);
+        assert_eq!(dependencies.down_parent(), None);
+        let independent = Dependencies::new(&target(), states);
+        assert_eq!(independent.down_parent(), None);
+    }
+
+    #[tokio::test]
+    async fn publishes_the_health_of_a_flapping_target() {
+        let parent: Target = serde_yaml::from_str(
+            "name: db\nurl: http://localhost\nfailure_threshold: 1\nrecovery_threshold: 1\nflapping:\n  window: 3\n  ratio: 0.5",
+        )
+        .unwrap();
+        let (results, receiver) = broadcast::channel(8);
+        let (changes, mut received) = broadcast::channel(8);
+        let states = states(&[]);
+        let tracker = StateTrackerTask::new(receiver, changes, states.clone());
+        tokio::spawn(tracker.run(parent.clone()));
+        let dependencies = Dependencies::new(&dependent("db"), states);
+        for success in &[false, true, false] {
+            let result = if *success {
+                Ok(Entry::new(
+                    Utc::now(),
+                    10,
+                    Some(200),
+                    Timings::default(),
+                    None,
+                    1,
+                    parent.clone(),
+                )
+                .to_dto())
+            } else {
+                Err(failure(&parent, false).to_dto())
+            };
+            results.send(result).unwrap();
+        }
+        // down, up and flapping while it is down again
+        let mut change = received.recv().await.unwrap();
+        for _ in 0..2 {
+            change = received.recv().await.unwrap();
+        }
+        assert_eq!(change.to, TargetState::Flapping);
+        assert_eq!(dependencies.down_parent(), Some(String::from("db")));
+    }
+
+    fn failure(target: &Target, suppressed: bool) -> Failure {
+        let mut failure = Failure::new(
+            Utc::now(),
+            10,
+            String::from("refused"),
+            None,
+            1,
+            target.clone(),
+        );
+        failure.suppressed = suppressed;
+        failure
+    }
+
+    #[tokio::test]
+    async fn ignores_suppressed_failures() {
+        let target = target();
+        let (results, receiver) = broadcast::channel(8);
+        let (changes, mut received) = broadcast::channel(8);
+        let tracker = StateTrackerTask::new(receiver, changes, states(&[]));
+        tokio::spawn(tracker.run(target.clone()));
+        for _ in 0..3 {
+            results.send(Err(failure(&target, true).to_dto())).unwrap();
+        }
+        results.send(Err(failure(&target, false).to_dto())).unwrap();
+        let change = received.recv().await.unwrap();
+        assert_eq!(
+            (change.from, change.to),
+            (TargetState::Unknown, TargetState::Degraded)
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{
+    config::{Target, TcpOptions},
+    messages::{Entry, Failure, Timings},
+    tasks::probe::Probe,
+    utils::net,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tokio::net::{lookup_host, TcpStream};
+use tokio::prelude::*;
+
+pub struct TcpProbe {
+    host: String,
+    port: u16,
+    options: TcpOptions,
+}
+
+impl TcpProbe {
+    pub fn new(target: &Target) -> Result<Self, String> {
+        let (host, port) = net::host_and_port(&target.url, "tcp")?;
+        let options = target.tcp.clone().unwrap_or_default();
+
+        Ok(Self {
+            host,
+            port,
+            options,
+        })
+    }
+
+    async fn check(&self, timings: &mut Timings) -> Result<(), String> {
+        let phase = Instant::now();
+        let addrs: Vec<SocketAddr> = lookup_host((self.host.as_str(), self.port))
+            .await
+            .map_err(|err| format!("dns lookup failed: {}", err))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(format!(
+                "dns lookup for {} returned no addresses",
+                self.host
+            ));
+        }
+        timings.dns = phase.elapsed().as_millis();
+
+        let phase = Instant::now();
+        let mut stream = net::connect_any(&addrs)
+            .await
+            .map_err(|err| format!("tcp connect failed: {}", err))?;
+        timings.connect = phase.elapsed().as_millis();
+
+        let phase = Instant::now();
+        let mut received = Vec::new();
+        if let Some(banner) = &self.options.banner {
+            Self::read_until(&mut stream, &mut received, |r| r.len() >= banner.len()).await?;
+            timings.ttfb = phase.elapsed().as_millis();
+            if !received.starts_with(banner.as_bytes()) {
+                return Err(format!(
+                    "unexpected banner '{}'",
+                    String::from_utf8_lossy(&received).trim()
+                ));
+            }
+        }
+        if let Some(send) = &self.options.send {
+            stream
+                .write_all(send.as_bytes())
+                .await
+                .map_err(|err| format!("failed to send: {}", err))?;
+        }
+        if let Some(expect) = &self.options.expect {
+            let mut reply = Vec::new();
+            Self::read_until(&mut stream, &mut reply, |r| contains(r, expect.as_bytes())).await?;
+            if timings.ttfb == 0 {
+                timings.ttfb = phase.elapsed().as_millis();
+            }
+            if !contains(&reply, expect.as_bytes()) {
+                return Err(format!(
+                    "expected '{}' got '{}'",
+                    expect,
+                    String::from_utf8_lossy(&reply).trim()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // reads from the stream until done returns true or the connection is closed
+    async fn read_until<F>(
+        stream: &mut TcpStream,
+        received: &mut Vec<u8>,
+        done: F,
+    ) -> Result<(), String>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let mut buffer = [0; 1024];
+        while !done(received) {
+            let n = stream
+                .read(&mut buffer)
+                .await
+                .map_err(|err| format!("failed to read: {}", err))?;
+            if n == 0 {
+                break;
+            }
+            received.extend_from_slice(&buffer[..n]);
+        }
+
+        Ok(())
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[async_trait]
+impl Probe for TcpProbe {
+    async fn execute(&self, target: &Target) -> Result<Entry, Failure> {
+        let started = Instant::now();
+        let mut timings = Timings::default();
+        match self.check(&mut timings).await {
+            Ok(_) => Ok(Entry::new(
+                Utc::now(),
+                started.elapsed().as_millis(),
+                None,
+                timings,
+                None,
+                1,
+                target.clone(),
+            )),
+            Err(reason) => Err(Failure::new(
+                Utc::now(),
+                started.elapsed().as_millis(),
+                reason,
+                None,
+                1,
+                target.clone(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn target(port: u16, options: &str) -> Target {
+        let yaml = format!("type: tcp\nurl: 127.0.0.1:{}\ntcp:\n{}", port, options);
+        serde_yaml::from_str::<Target>(&yaml).unwrap().hydrate()
+    }
+
+    async fn probe(target: &Target) -> Result<Entry, Failure> {
+        TcpProbe::new(target).unwrap().execute(target).await
+    }
+
+    // greets each connection with the banner and answers "PING" with "PONG"
+    async fn server(banner: &'static str) -> u16 {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    stream.write_all(banner.as_bytes()).await.unwrap();
+                    let mut buffer = [0; 64];
+                    while let Ok(n) = stream.read(&mut buffer).await {
+                        if n == 0 {
+                            break;
+                        }
+                        if buffer[..n].starts_with(b"PING") {
+                            stream.write_all(b"+PONG\r\n").await.unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn connects() {
+        let port = server("").await;
+        assert!(probe(&target(port, "  {}")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn fails_on_refused_connections() {
+        // the port is free again once the listener is dropped
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let failure = probe(&target(port, "  {}")).await.unwrap_err();
+        assert!(
+            failure.reason.starts_with("tcp connect failed"),
+            "{}",
+            failure.reason
+        );
+    }
+
+    #[tokio::test]
+    async fn matches_the_banner() {
+        let port = server("SSH-2.0-OpenSSH\r\n").await;
+        assert!(probe(&target(port, "  banner: SSH-")).await.is_ok());
+        let failure = probe(&target(port, "  banner: \"220 \""))
+            .await
+            .unwrap_err();
+        assert_eq!(failure.reason, "unexpected banner 'SSH-2.0-OpenSSH'");
+    }
+
+    #[tokio::test]
+    async fn sends_and_expects_a_reply() {
+        let port = server("").await;
+        let options = "  send: \"PING\\r\\n\"\n  expect: PONG";
+        assert!(probe(&target(port, options)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_unexpected_reply() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 6];
+            stream.read_exact(&mut buffer).await.unwrap();
+            // the connection is closed after the reply
+            stream.write_all(b"-ERR unknown\r\n").await.unwrap();
+        });
+        let options = "  send: \"PING\\r\\n\"\n  expect: PONG";
+        let failure = probe(&target(port, options)).await.unwrap_err();
+        assert_eq!(failure.reason, "expected 'PONG' got '-ERR unknown'");
+    }
+}
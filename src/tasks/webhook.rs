@@ -0,0 +1,235 @@
+use crate::{
+    config::notification::{NotifyOn, Webhook},
+    messages::Notification,
+    tasks::notifier::{self, Notifier},
+    utils::template,
+};
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use std::time::Duration;
+
+// sends notifications as http requests
+pub struct WebhookNotifier {
+    webhook: Webhook,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(webhook: Webhook) -> Self {
+        Self {
+            webhook,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // the configured payload with the values filled in, or a json object of all values
+    fn payload(&self, notification: &Notification) -> String {
+        let values = notification.values();
+        match &self.webhook.payload {
+            Some(payload) => {
+                // values are escaped to fit inside json strings
+                let escaped: Vec<(&str, String)> = values
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let quoted = serde_json::Value::String(value).to_string();
+                        (name, quoted[1..quoted.len() - 1].to_string())
+                    })
+                    .collect();
+                template::render(payload, &escaped)
+            }
+            None => {
+                let object: serde_json::Map<String, serde_json::Value> = values
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), serde_json::Value::String(value)))
+                    .collect();
+                serde_json::Value::Object(object).to_string()
+            }
+        }
+    }
+
+    async fn send(&self, body: String, attempt_timeout: Duration) -> Result<(), String> {
+        let mut request = self
+            .client
+            .request(
+                self.webhook.clone_unwrap_method().to_reqwest_method(),
+                &self.webhook.url,
+            )
+            .timeout(attempt_timeout)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        for (name, value) in self.webhook.headers.iter().flatten() {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(format!("responded with {}", response.status())),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn notifies_on(&self, on: NotifyOn) -> bool {
+        self.webhook.notifies_on(on)
+    }
+
+    fn is_escalation(&self) -> bool {
+        self.webhook.is_escalation()
+    }
+
+    fn describe(&self) -> String {
+        format!("webhook {}", self.webhook.url)
+    }
+
+    // redelivers failed requests as configured by the retries of the webhook
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        let body = self.payload(notification);
+        let describe = format!("{} - {}", notification.target.describe(), self.describe());
+        notifier::deliver(
+            &describe,
+            self.webhook.retries.as_ref(),
+            self.webhook.unwrap_timeout(),
+            |attempt_timeout| self.send(body.clone(), attempt_timeout),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio::prelude::*;
+
+    fn webhook(url: &str, config: &str) -> WebhookNotifier {
+        let yaml = format!("url: {}\n{}", url, config);
+        WebhookNotifier::new(serde_yaml::from_str(&yaml).unwrap())
+    }
+
+    fn notification() -> Notification {
+        let target: Target = serde_yaml::from_str(
+            "name: api\nurl: https://api.example.com\ntags: [production, eu]\nseverity: critical",
+        )
+        .unwrap();
+        Notification {
+            on: NotifyOn::Failure,
+            time: Utc.ymd(2020, 5, 1).and_hms(12, 30, 0),
+            state: None,
+            previous_state: None,
+            reason: Some(String::from("expected \"up\" got \"down\"")),
+            latency: Some(5000),
+            response_code: Some(503),
+            down_since: None,
+            escalated: false,
+            suppressed: false,
+            target: target.hydrate(),
+        }
+    }
+
+    #[test]
+    fn sends_all_values_by_default() {
+        let notifier = webhook("http://localhost", "");
+        let payload: Value = serde_json::from_str(&notifier.payload(&notification())).unwrap();
+        assert_eq!(payload["name"], "api");
+        assert_eq!(payload["tags"], "production,eu");
+        assert_eq!(payload["severity"], "critical");
+        assert_eq!(payload["event"], "failure");
+        assert_eq!(payload["reason"], "expected \"up\" got \"down\"");
+        assert_eq!(payload["response_code"], "503");
+        assert_eq!(payload["down_since"], "");
+    }
+
+    #[test]
+    fn fills_in_the_payload() {
+        let notifier = webhook(
+            "http://localhost",
+            "payload: '{\"text\": \"{{name}} is down: {{reason}}\"}'",
+        );
+        let payload: Value = serde_json::from_str(&notifier.payload(&notification())).unwrap();
+        // the quotes of the reason are escaped so the payload stays valid json
+        assert_eq!(payload["text"], "api is down: expected \"up\" got \"down\"");
+    }
+
+    // answers each request with the next status and keeps the requests it received
+    async fn stand_in(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://127.0.0.1:{}/hook",
+            listener.local_addr().unwrap().port()
+        );
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                // the request is complete once the body has the announced length
+                let request = loop {
+                    let n = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length: usize = text[..end]
+                            .lines()
+                            .map(|l| l.to_lowercase())
+                            .find(|l| l.starts_with("content-length: "))
+                            .map(|l| l["content-length: ".len()..].parse().unwrap())
+                            .unwrap_or(0);
+                        if text.len() >= end + 4 + length {
+                            break text;
+                        }
+                    }
+                };
+                received.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn sends_the_configured_request() {
+        let (url, requests) = stand_in(vec![200]).await;
+        let notifier = webhook(
+            &url,
+            "method: PUT\nheaders:\n  x-token: secret\npayload: '{\"name\": \"{{name}}\"}'",
+        );
+        notifier.notify(&notification()).await.unwrap();
+        let requests = requests.lock().unwrap();
+        let request = requests[0].to_lowercase();
+        assert!(request.starts_with("put /hook http/1.1\r\n"));
+        assert!(request.contains("\r\ncontent-type: application/json\r\n"));
+        assert!(request.contains("\r\nx-token: secret\r\n"));
+        assert!(request.ends_with("\r\n\r\n{\"name\": \"api\"}"));
+    }
+
+    #[tokio::test]
+    async fn retries_until_accepted() {
+        let (url, requests) = stand_in(vec![500, 502, 204]).await;
+        let notifier = webhook(&url, "retries:\n  count: 2\n  delay: 10ms");
+        notifier.notify(&notification()).await.unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn fails_after_the_last_retry() {
+        let (url, requests) = stand_in(vec![500, 500]).await;
+        let notifier = webhook(&url, "retries:\n  count: 1\n  delay: 10ms");
+        let err = notifier.notify(&notification()).await.unwrap_err();
+        assert_eq!(
+            err,
+            "responded with 500 Internal Server Error after 2 attempts"
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
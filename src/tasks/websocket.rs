@@ -0,0 +1,265 @@
+use crate::{
+    config::{Target, WebsocketOptions},
+    messages::{Entry, Failure, Timings},
+    tasks::probe::Probe,
+    utils::net,
+};
+use async_native_tls::TlsConnector;
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::{SinkExt, StreamExt};
+use hyper::Request;
+use reqwest::Url;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::lookup_host;
+use tokio_tungstenite::{client_async, tungstenite::Message};
+
+pub struct WebsocketProbe {
+    url: Url,
+    options: WebsocketOptions,
+}
+
+impl WebsocketProbe {
+    pub fn new(target: &Target) -> Result<Self, String> {
+        let url = Url::parse(&target.url).map_err(|err| format!("invalid url: {}", err))?;
+        if url.scheme() != "ws" && url.scheme() != "wss" {
+            return Err(format!("unsupported websocket scheme {}", url.scheme()));
+        }
+
+        Ok(Self {
+            url,
+            options: target.websocket.clone().unwrap_or_default(),
+        })
+    }
+
+    async fn check(&self, target: &Target, timings: &mut Timings) -> Result<(), String> {
+        // ipv6 addresses are looked up and verified without their brackets
+        let host = self
+            .url
+            .host_str()
+            .ok_or_else(|| String::from("url has no host"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = self
+            .url
+            .port_or_known_default()
+            .ok_or_else(|| String::from("url has no port"))?;
+
+        let mut request = Request::builder().uri(self.url.as_str());
+        if let Some(headers) = &target.headers {
+            for (name, value) in headers {
+                request = request.header(name.as_str(), value.as_str());
+            }
+        }
+        let request = request
+            .body(())
+            .map_err(|err| format!("invalid request: {}", err))?;
+
+        let phase = Instant::now();
+        let addrs: Vec<SocketAddr> = lookup_host((host, port))
+            .await
+            .map_err(|err| format!("dns lookup failed: {}", err))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(format!("dns lookup for {} returned no addresses", host));
+        }
+        timings.dns = phase.elapsed().as_millis();
+
+        let phase = Instant::now();
+        let stream = net::connect_any(&addrs)
+            .await
+            .map_err(|err| format!("tcp connect failed: {}", err))?;
+        timings.connect = phase.elapsed().as_millis();
+
+        if self.url.scheme() == "wss" {
+            let phase = Instant::now();
+            let stream = TlsConnector::new()
+                .connect(host, stream)
+                .await
+                .map_err(|err| format!("tls handshake failed: {}", err))?;
+            timings.tls = phase.elapsed().as_millis();
+            self.session(stream, request, timings).await
+        } else {
+            self.session(stream, request, timings).await
+        }
+    }
+
+    // upgrades the connection, then sends the message and waits for the expected reply
+    async fn session<S>(
+        &self,
+        stream: S,
+        request: Request<()>,
+        timings: &mut Timings,
+    ) -> Result<(), String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let phase = Instant::now();
+        let (mut socket, _) = client_async(request, stream)
+            .await
+            .map_err(|err| format!("websocket handshake failed: {}", err))?;
+        timings.ttfb = phase.elapsed().as_millis();
+
+        let phase = Instant::now();
+        if let Some(send) = &self.options.send {
+            socket
+                .send(Message::Text(send.clone()))
+                .await
+                .map_err(|err| format!("failed to send: {}", err))?;
+        }
+        if let Some(expect) = &self.options.expect {
+            loop {
+                let message = match socket.next().await {
+                    Some(message) => message.map_err(|err| format!("failed to read: {}", err))?,
+                    None => return Err(format!("connection closed before receiving '{}'", expect)),
+                };
+                let received = match message {
+                    Message::Text(text) => text,
+                    Message::Binary(data) => String::from_utf8_lossy(&data).to_string(),
+                    Message::Close(_) => {
+                        return Err(format!("connection closed before receiving '{}'", expect))
+                    }
+                    Message::Ping(_) | Message::Pong(_) => continue,
+                };
+                if received.contains(expect.as_str()) {
+                    break;
+                }
+            }
+            timings.body = phase.elapsed().as_millis();
+        }
+        // the check already passed, a failing close doesn't change that
+        let _ = socket.close(None).await;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Probe for WebsocketProbe {
+    async fn execute(&self, target: &Target) -> Result<Entry, Failure> {
+        let started = Instant::now();
+        let mut timings = Timings::default();
+        match self.check(target, &mut timings).await {
+            Ok(_) => Ok(Entry::new(
+                Utc::now(),
+                started.elapsed().as_millis(),
+                None,
+                timings,
+                None,
+                1,
+                target.clone(),
+            )),
+            Err(reason) => Err(Failure::new(
+                Utc::now(),
+                started.elapsed().as_millis(),
+                reason,
+                None,
+                1,
+                target.clone(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::prelude::*;
+    use tokio_tungstenite::accept_async;
+
+    fn target(url: &str, options: &str) -> Target {
+        let yaml = format!("type: websocket\nurl: {}\nwebsocket:\n{}", url, options);
+        serde_yaml::from_str::<Target>(&yaml).unwrap().hydrate()
+    }
+
+    async fn probe(target: &Target) -> Result<Entry, Failure> {
+        WebsocketProbe::new(target).unwrap().execute(target).await
+    }
+
+    // upgrades each connection and echoes the first message before closing
+    async fn echo_server(address: &str) -> u16 {
+        let mut listener = TcpListener::bind(address).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut socket = accept_async(stream).await.unwrap();
+                    if let Some(Ok(message)) = socket.next().await {
+                        let _ = socket.send(message).await;
+                    }
+                    let _ = socket.close(None).await;
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn upgrades_the_connection() {
+        let port = echo_server("127.0.0.1:0").await;
+        let url = format!("ws://127.0.0.1:{}/socket", port);
+        assert!(probe(&target(&url, "  {}")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn receives_the_expected_reply() {
+        let port = echo_server("127.0.0.1:0").await;
+        let url = format!("ws://127.0.0.1:{}/socket", port);
+        let options = "  send: ping\n  expect: ping";
+        assert!(probe(&target(&url, options)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_unexpected_reply() {
+        let port = echo_server("127.0.0.1:0").await;
+        let url = format!("ws://127.0.0.1:{}/socket", port);
+        let options = "  send: ping\n  expect: pong";
+        let failure = probe(&target(&url, options)).await.unwrap_err();
+        assert_eq!(failure.reason, "connection closed before receiving 'pong'");
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_server_does_not_upgrade() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 1024];
+            let _ = stream.read(&mut buffer).await.unwrap();
+            let response = "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n";
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        let url = format!("ws://127.0.0.1:{}/socket", port);
+        let failure = probe(&target(&url, "  {}")).await.unwrap_err();
+        assert!(failure.reason.starts_with("websocket handshake failed"));
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_connection_is_refused() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("ws://127.0.0.1:{}/socket", port);
+        let failure = probe(&target(&url, "  {}")).await.unwrap_err();
+        assert!(failure.reason.starts_with("tcp connect failed"));
+    }
+
+    #[tokio::test]
+    async fn connects_to_ipv6_addresses() {
+        // skipped where the loopback has no ipv6 address
+        if TcpListener::bind("[::1]:0").await.is_err() {
+            return;
+        }
+        let port = echo_server("[::1]:0").await;
+        let url = format!("ws://[::1]:{}/socket", port);
+        let options = "  send: ping\n  expect: ping";
+        assert!(probe(&target(&url, options)).await.is_ok());
+    }
+}
@@ -61,6 +61,332 @@ pub mod file {
     impl Append for tokio::fs::File {}
 }
 
+pub mod hash {
+    // fnv-1a, unlike the std hasher it gives the same value across builds and platforms
+    pub fn stable_hash(value: &str) -> u64 {
+        value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+}
+
+pub mod template {
+    // replaces each {{name}} in the template with its value
+    pub fn render(template: &str, values: &[(&str, String)]) -> String {
+        values
+            .iter()
+            .fold(template.to_string(), |rendered, (name, value)| {
+                rendered.replace(&format!("{{{{{}}}}}", name), value)
+            })
+    }
+}
+
+pub mod pattern {
+    // matches a value against a pattern where * stands for any number of characters, e.g. "api-*"
+    pub fn matches(pattern: &str, value: &str) -> bool {
+        let mut parts = pattern.split('*');
+        // split always returns at least one part
+        let first = parts.next().unwrap_or_default();
+        let mut rest = match value.strip_prefix(first) {
+            Some(rest) => rest,
+            None => return false,
+        };
+        let parts: Vec<&str> = parts.collect();
+        let last = match parts.split_last() {
+            Some((last, middle)) => {
+                // each part between two stars is matched at its first occurrence
+                for part in middle {
+                    match rest.find(part) {
+                        Some(index) => rest = &rest[index + part.len()..],
+                        None => return false,
+                    }
+                }
+                last
+            }
+            // no star, the whole value must be the pattern
+            None => return rest.is_empty(),
+        };
+        rest.ends_with(last)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn matches_stars() {
+            let cases = [
+                ("api", "api", true),
+                ("api", "api-eu", false),
+                ("*", "", true),
+                ("*", "anything", true),
+                ("api-*", "api-eu", true),
+                ("api-*", "web-eu", false),
+                ("*-eu", "api-eu", true),
+                ("*-eu", "api-us", false),
+                ("api-*-prod", "api-eu-prod", true),
+                ("api-*-prod", "api-prod", false),
+                ("*eu*", "api-eu-prod", true),
+                ("a*b*c", "abbc", true),
+                ("a*b*c", "acb", false),
+            ];
+            for (pattern, value, expected) in cases.iter() {
+                assert_eq!(matches(pattern, value), *expected, "{} {}", pattern, value);
+            }
+        }
+    }
+}
+
+pub mod schedule {
+    use cron::Schedule;
+    use std::str::FromStr;
+
+    // parses a cron expression. The seconds field is optional, the common five field form starts at second 0
+    pub fn parse_cron(expression: &str) -> Result<Schedule, String> {
+        let expression = if expression.split_whitespace().count() == 5 {
+            format!("0 {}", expression)
+        } else {
+            expression.to_string()
+        };
+        Schedule::from_str(&expression).map_err(|err| err.to_string())
+    }
+}
+
+pub mod net {
+    use reqwest::Url;
+    use std::net::{IpAddr, SocketAddr};
+    use tokio::net::TcpStream;
+
+    // splits an address like host:port or scheme://host:port into host and port
+    pub fn host_and_port(address: &str, scheme: &str) -> Result<(String, u16), String> {
+        let prefix = format!("{}://", scheme);
+        let address = if address.starts_with(&prefix) {
+            address.to_string()
+        } else {
+            format!("{}{}", prefix, address)
+        };
+        let url = Url::parse(&address).map_err(|err| format!("invalid address: {}", err))?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| String::from("address has no host"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = url
+            .port()
+            .ok_or_else(|| String::from("address has no port"))?;
+
+        Ok((host, port))
+    }
+
+    // host:port for a uri, ipv6 addresses go back in brackets
+    pub fn authority(host: &str, port: u16) -> String {
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => format!("[{}]:{}", host, port),
+            _ => format!("{}:{}", host, port),
+        }
+    }
+
+    // the proxy from HTTP_PROXY, HTTPS_PROXY or ALL_PROXY for the url, unless its host is in NO_PROXY
+    pub fn proxy_from_env(url: &Url) -> Option<String> {
+        proxy_for(url, |name| std::env::var(name).ok())
+    }
+
+    fn proxy_for<F: Fn(&str) -> Option<String>>(url: &Url, env: F) -> Option<String> {
+        let var = |name: &str| {
+            env(name)
+                .or_else(|| env(&name.to_lowercase()))
+                .filter(|value| !value.is_empty())
+        };
+        let scheme = url.scheme();
+        // in cgi a client can set HTTP_PROXY with a Proxy header
+        let proxy = if scheme == "http" && env("REQUEST_METHOD").is_some() {
+            None
+        } else {
+            var(&format!("{}_PROXY", scheme.to_uppercase()))
+        };
+        let proxy = proxy.or_else(|| var("ALL_PROXY"))?;
+        let host = url
+            .host_str()?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_lowercase();
+        let bypassed = var("NO_PROXY").is_some_and(|no_proxy| {
+            no_proxy
+                .split(',')
+                .map(|entry| entry.trim().to_lowercase())
+                .any(|entry| bypasses(&entry, &host))
+        });
+        if bypassed {
+            return None;
+        }
+        Some(proxy)
+    }
+
+    // entries are *, a domain that also covers its subdomains, an ip or a network like 10.0.0.0/8
+    fn bypasses(entry: &str, host: &str) -> bool {
+        if entry == "*" {
+            return true;
+        }
+        if let (Some((network, bits)), Ok(ip)) = (split_network(entry), host.parse::<IpAddr>()) {
+            return in_network(ip, network, bits);
+        }
+        let domain = entry.trim_start_matches("*.").trim_start_matches('.');
+        !domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain)))
+    }
+
+    fn split_network(entry: &str) -> Option<(IpAddr, u32)> {
+        let mut parts = entry.splitn(2, '/');
+        let ip = parts.next()?.parse::<IpAddr>().ok()?;
+        let bits = match (parts.next(), ip) {
+            (Some(bits), _) => bits.parse().ok()?,
+            (None, IpAddr::V4(_)) => 32,
+            (None, IpAddr::V6(_)) => 128,
+        };
+        Some((ip, bits))
+    }
+
+    fn in_network(ip: IpAddr, network: IpAddr, bits: u32) -> bool {
+        match (ip, network) {
+            (IpAddr::V4(ip), IpAddr::V4(network)) if bits <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+                u32::from(ip) & mask == u32::from(network) & mask
+            }
+            (IpAddr::V6(ip), IpAddr::V6(network)) if bits <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+                u128::from(ip) & mask == u128::from(network) & mask
+            }
+            _ => false,
+        }
+    }
+
+    // tries each address in turn and returns the first connection that succeeds
+    pub async fn connect_any(addrs: &[SocketAddr]) -> tokio::io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in addrs {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            tokio::io::Error::new(tokio::io::ErrorKind::NotFound, "no addresses to connect to")
+        }))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn proxy(url: &str, vars: &[(&str, &str)]) -> Option<String> {
+            let url = Url::parse(url).unwrap();
+            proxy_for(&url, |name| {
+                vars.iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.to_string())
+            })
+        }
+
+        #[test]
+        fn brackets_ipv6_hosts() {
+            assert_eq!(
+                host_and_port("grpc://[::1]:50051", "grpc"),
+                Ok((String::from("::1"), 50051))
+            );
+            assert_eq!(authority("::1", 50051), "[::1]:50051");
+            assert_eq!(authority("127.0.0.1", 50051), "127.0.0.1:50051");
+            assert_eq!(authority("localhost", 50051), "localhost:50051");
+        }
+
+        #[test]
+        fn picks_the_proxy_of_the_scheme() {
+            let vars = [
+                ("HTTP_PROXY", "http://plain:3128"),
+                ("https_proxy", "http://secure:3128"),
+            ];
+            assert_eq!(
+                proxy("http://example.com", &vars).unwrap(),
+                "http://plain:3128"
+            );
+            assert_eq!(
+                proxy("https://example.com", &vars).unwrap(),
+                "http://secure:3128"
+            );
+            let vars = [("ALL_PROXY", "socks5://all:1080")];
+            assert_eq!(
+                proxy("https://example.com", &vars).unwrap(),
+                "socks5://all:1080"
+            );
+            assert_eq!(proxy("https://example.com", &[]), None);
+        }
+
+        #[test]
+        fn ignores_http_proxy_in_cgi() {
+            let vars = [("HTTP_PROXY", "http://evil:80"), ("REQUEST_METHOD", "GET")];
+            assert_eq!(proxy("http://example.com", &vars), None);
+        }
+
+        #[test]
+        fn bypasses_hosts_in_no_proxy() {
+            let vars = [
+                ("ALL_PROXY", "http://proxy:3128"),
+                (
+                    "NO_PROXY",
+                    "localhost, .internal,example.org,10.0.0.0/8,::1",
+                ),
+            ];
+            assert_eq!(proxy("http://localhost:8080", &vars), None);
+            assert_eq!(proxy("http://api.internal", &vars), None);
+            assert_eq!(proxy("http://internal", &vars), None);
+            assert_eq!(proxy("http://www.example.org", &vars), None);
+            assert_eq!(proxy("http://10.1.2.3", &vars), None);
+            assert_eq!(proxy("http://[::1]:80", &vars), None);
+            assert!(proxy("http://notexample.org", &vars).is_some());
+            assert!(proxy("http://11.0.0.1", &vars).is_some());
+            assert_eq!(
+                proxy("http://anything", &[("HTTP_PROXY", "p"), ("no_proxy", "*")]),
+                None
+            );
+        }
+    }
+}
+
+pub mod tls {
+    use async_native_tls::{Certificate, TlsConnector};
+    use chrono::{DateTime, TimeZone, Utc};
+    use tokio::net::TcpStream;
+    use x509_parser::parse_x509_certificate;
+
+    // Connects to the host and returns when its certificate expires.
+    // Invalid certificates are accepted, so the expiry of an already expired certificate can still be read
+    pub async fn peer_certificate_expiry(host: &str, port: u16) -> Result<DateTime<Utc>, String> {
+        let stream = TcpStream::connect((host, port))
+            .await
+            .map_err(|err| format!("failed to connect: {}", err))?;
+        let stream = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .connect(host, stream)
+            .await
+            .map_err(|err| format!("tls handshake failed: {}", err))?;
+        let certificate = stream
+            .peer_certificate()
+            .map_err(|err| format!("failed to get peer certificate: {}", err))?
+            .ok_or_else(|| String::from("no peer certificate"))?;
+
+        certificate_expiry(&certificate)
+    }
+
+    pub fn certificate_expiry(certificate: &Certificate) -> Result<DateTime<Utc>, String> {
+        let der = certificate
+            .to_der()
+            .map_err(|err| format!("failed to encode peer certificate: {}", err))?;
+        let (_, certificate) = parse_x509_certificate(&der)
+            .map_err(|err| format!("failed to parse peer certificate: {}", err))?;
+
+        Ok(Utc.timestamp(certificate.validity().not_after.timestamp(), 0))
+    }
+}
+
 pub mod prometheus {
     pub fn normalize_name(s: String) -> String {
         s.replace('-', "_").replace('.', "_")
@@ -70,7 +396,39 @@ pub mod prometheus {
         normalize_name(s + "_success")
     }
 
+    pub fn counter_retries_name(s: String) -> String {
+        normalize_name(s + "_retries")
+    }
+
     pub fn timer_name(name: String) -> String {
         normalize_name(name + "_time_ms")
     }
+
+    pub fn phase_timer_name(name: String, phase: &str) -> String {
+        normalize_name(format!("{}_{}_time_ms", name, phase))
+    }
+
+    pub fn cert_expiry_name(name: String) -> String {
+        normalize_name(name + "_cert_expiry_seconds")
+    }
+
+    pub fn interval_name(name: String) -> String {
+        normalize_name(name + "_interval_seconds")
+    }
+
+    pub fn in_maintenance_name(name: String) -> String {
+        normalize_name(name + "_in_maintenance")
+    }
+
+    pub fn up_name(name: String) -> String {
+        normalize_name(name + "_up")
+    }
+
+    pub fn flapping_name(name: String) -> String {
+        normalize_name(name + "_flapping")
+    }
+
+    pub fn counter_state_changes_name(name: String) -> String {
+        normalize_name(name + "_state_changes")
+    }
 }